@@ -0,0 +1,631 @@
+use std::{fmt::Display, str::FromStr, time::Duration};
+
+use crate::{chunk::Chunk, chunk_type::ChunkType, encoder, ihdr::Ihdr, png::Png};
+
+/// Chunks that describe the animation rather than the still image.
+pub const ANIMATION_CHUNKS: [&str; 3] = ["acTL", "fcTL", "fdAT"];
+
+/// The contents of an acTL chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnimationControl {
+    pub num_frames: u32,
+    /// How many times to play the animation; 0 loops forever.
+    pub num_plays: u32,
+}
+
+impl TryFrom<&[u8]> for AnimationControl {
+    type Error = &'static str;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        if value.len() != 8 {
+            return Err("acTL must be 8 bytes long");
+        }
+        Ok(AnimationControl {
+            num_frames: u32::from_be_bytes(value[0..4].try_into().unwrap()),
+            num_plays: u32::from_be_bytes(value[4..8].try_into().unwrap()),
+        })
+    }
+}
+
+impl AnimationControl {
+    pub fn as_bytes(&self) -> Vec<u8> {
+        self.num_frames
+            .to_be_bytes()
+            .into_iter()
+            .chain(self.num_plays.to_be_bytes())
+            .collect()
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new(ChunkType::from_str("acTL").unwrap(), self.as_bytes())
+    }
+}
+
+/// What happens to the frame region before the next frame is drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisposeOp {
+    None = 0,
+    Background = 1,
+    Previous = 2,
+}
+
+impl TryFrom<u8> for DisposeOp {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(DisposeOp::None),
+            1 => Ok(DisposeOp::Background),
+            2 => Ok(DisposeOp::Previous),
+            _ => Err("unknown dispose op"),
+        }
+    }
+}
+
+impl Display for DisposeOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            DisposeOp::None => "none",
+            DisposeOp::Background => "background",
+            DisposeOp::Previous => "previous",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// How the frame is drawn over the output buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendOp {
+    Source = 0,
+    Over = 1,
+}
+
+impl TryFrom<u8> for BlendOp {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(BlendOp::Source),
+            1 => Ok(BlendOp::Over),
+            _ => Err("unknown blend op"),
+        }
+    }
+}
+
+impl Display for BlendOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            BlendOp::Source => "source",
+            BlendOp::Over => "over",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// The contents of an fcTL chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameControl {
+    pub sequence_number: u32,
+    pub width: u32,
+    pub height: u32,
+    pub x_offset: u32,
+    pub y_offset: u32,
+    pub delay_num: u16,
+    /// A denominator of 0 means hundredths of a second.
+    pub delay_den: u16,
+    pub dispose_op: DisposeOp,
+    pub blend_op: BlendOp,
+}
+
+impl TryFrom<&[u8]> for FrameControl {
+    type Error = &'static str;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        if value.len() != 26 {
+            return Err("fcTL must be 26 bytes long");
+        }
+        let u32_at = |at: usize| u32::from_be_bytes(value[at..at + 4].try_into().unwrap());
+        let u16_at = |at: usize| u16::from_be_bytes(value[at..at + 2].try_into().unwrap());
+
+        let control = FrameControl {
+            sequence_number: u32_at(0),
+            width: u32_at(4),
+            height: u32_at(8),
+            x_offset: u32_at(12),
+            y_offset: u32_at(16),
+            delay_num: u16_at(20),
+            delay_den: u16_at(22),
+            dispose_op: DisposeOp::try_from(value[24])?,
+            blend_op: BlendOp::try_from(value[25])?,
+        };
+        if control.width == 0 || control.height == 0 {
+            return Err("frame dimensions must be non-zero");
+        }
+        Ok(control)
+    }
+}
+
+impl FrameControl {
+    pub fn delay(&self) -> Duration {
+        let den = match self.delay_den {
+            0 => 100,
+            den => den,
+        };
+        Duration::from_secs_f64(self.delay_num as f64 / den as f64)
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        [
+            self.sequence_number,
+            self.width,
+            self.height,
+            self.x_offset,
+            self.y_offset,
+        ]
+        .iter()
+        .flat_map(|n| n.to_be_bytes())
+        .chain(self.delay_num.to_be_bytes())
+        .chain(self.delay_den.to_be_bytes())
+        .chain([self.dispose_op as u8, self.blend_op as u8])
+        .collect()
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new(ChunkType::from_str("fcTL").unwrap(), self.as_bytes())
+    }
+}
+
+/// The contents of an fdAT chunk: a sequence number and part of a frame's
+/// zlib stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameData {
+    pub sequence_number: u32,
+    pub data: Vec<u8>,
+}
+
+impl TryFrom<&[u8]> for FrameData {
+    type Error = &'static str;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        if value.len() < 4 {
+            return Err("fdAT must hold a sequence number");
+        }
+        Ok(FrameData {
+            sequence_number: u32::from_be_bytes(value[0..4].try_into().unwrap()),
+            data: value[4..].to_vec(),
+        })
+    }
+}
+
+impl FrameData {
+    pub fn to_chunk(&self) -> Chunk {
+        let data = self
+            .sequence_number
+            .to_be_bytes()
+            .into_iter()
+            .chain(self.data.iter().copied())
+            .collect();
+        Chunk::new(ChunkType::from_str("fdAT").unwrap(), data)
+    }
+}
+
+/// One frame of an animation and its whole zlib stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub control: FrameControl,
+    pub data: Vec<u8>,
+    /// Whether the frame is the IDAT image rather than fdAT chunks.
+    pub is_default_image: bool,
+}
+
+/// The frames of an APNG, in display order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Animation {
+    pub control: AnimationControl,
+    pub frames: Vec<Frame>,
+}
+
+impl TryFrom<&Png> for Animation {
+    type Error = crate::Error;
+
+    fn try_from(png: &Png) -> Result<Self, Self::Error> {
+        let control = png.chunk_by_type("acTL").ok_or("not an animated PNG")?;
+        let control = AnimationControl::try_from(control.data())?;
+
+        let mut frames: Vec<Frame> = Vec::new();
+        let mut seen_idat = false;
+        for chunk in png.chunks().iter() {
+            match &chunk.chunk_type().to_string()[..] {
+                "fcTL" => frames.push(Frame {
+                    control: FrameControl::try_from(chunk.data())?,
+                    data: Vec::new(),
+                    is_default_image: !seen_idat,
+                }),
+                "IDAT" => {
+                    seen_idat = true;
+                    if let Some(frame) = frames.last_mut().filter(|f| f.is_default_image) {
+                        frame.data.extend(chunk.data());
+                    }
+                }
+                "fdAT" => {
+                    let frame = frames.last_mut().ok_or("fdAT before any fcTL")?;
+                    if frame.is_default_image {
+                        return Err("fdAT in a frame that uses the IDAT image".into());
+                    }
+                    frame.data.extend(FrameData::try_from(chunk.data())?.data);
+                }
+                _ => (),
+            }
+        }
+        Ok(Animation { control, frames })
+    }
+}
+
+impl Animation {
+    /// Whether the IDAT image is shown as the first frame; otherwise it is
+    /// only the fallback for viewers without APNG support.
+    pub fn has_default_frame(&self) -> bool {
+        self.frames.first().is_some_and(|f| f.is_default_image)
+    }
+
+    /// The total duration of one play.
+    pub fn duration(&self) -> Duration {
+        self.frames.iter().map(|f| f.control.delay()).sum()
+    }
+
+    /// Frame `index` as a standalone PNG of the frame region, with the
+    /// palette and colour chunks of `png`. Offsets, disposal and blending
+    /// are not applied.
+    pub fn frame_png(&self, png: &Png, index: usize) -> crate::Result<Png> {
+        let frame = self.frames.get(index).ok_or("no such frame")?;
+        let ihdr = png.ihdr()?;
+        // Frames share the colour type, bit depth and interlacing of the IHDR.
+        let mut frame_ihdr = ihdr.as_bytes();
+        frame_ihdr[0..4].copy_from_slice(&frame.control.width.to_be_bytes());
+        frame_ihdr[4..8].copy_from_slice(&frame.control.height.to_be_bytes());
+
+        let mut chunks = vec![Ihdr::try_from(&frame_ihdr[..])?.to_chunk()];
+        chunks.extend(
+            png.chunks()
+                .into_iter()
+                .take_while(is_before_image)
+                .filter(|c| {
+                    let chunk_type = c.chunk_type().to_string();
+                    chunk_type != "IHDR" && !ANIMATION_CHUNKS.contains(&&chunk_type[..])
+                }),
+        );
+        chunks.extend(encoder::split_idat(
+            &frame.data,
+            encoder::Encoder::DEFAULT_IDAT_SIZE,
+        ));
+        chunks.push(Chunk::new(ChunkType::from_str("IEND")?, Vec::new()));
+        Ok(Png::from_chunks(chunks))
+    }
+}
+
+fn is_before_image(chunk: &Chunk) -> bool {
+    !["IDAT", "fdAT", "IEND"].contains(&&chunk.chunk_type().to_string()[..])
+}
+
+/// Problems with the animation chunks of `png`: sequence numbers that are
+/// not 0, 1, 2... in file order, a frame count that disagrees with acTL and
+/// frames that do not fit the canvas.
+pub fn validate(png: &Png) -> crate::Result<Vec<String>> {
+    let animation = Animation::try_from(png)?;
+    let ihdr = png.ihdr()?;
+    let mut problems = Vec::new();
+
+    let mut expected = 0;
+    for chunk in png.chunks().iter() {
+        let chunk_type = chunk.chunk_type().to_string();
+        let sequence_number = match &chunk_type[..] {
+            "fcTL" => FrameControl::try_from(chunk.data())?.sequence_number,
+            "fdAT" => FrameData::try_from(chunk.data())?.sequence_number,
+            _ => continue,
+        };
+        if sequence_number != expected {
+            problems.push(format!(
+                "{chunk_type} has sequence number {sequence_number}, expected {expected}"
+            ));
+        }
+        expected = sequence_number.wrapping_add(1);
+    }
+
+    if animation.control.num_frames as usize != animation.frames.len() {
+        problems.push(format!(
+            "acTL announces {} frames, found {}",
+            animation.control.num_frames,
+            animation.frames.len()
+        ));
+    }
+    for (i, frame) in animation.frames.iter().enumerate() {
+        let control = &frame.control;
+        if control.x_offset as u64 + control.width as u64 > ihdr.width() as u64
+            || control.y_offset as u64 + control.height as u64 > ihdr.height() as u64
+        {
+            problems.push(format!("frame {i} does not fit the canvas"));
+        }
+        if frame.data.is_empty() {
+            problems.push(format!("frame {i} has no image data"));
+        }
+    }
+    if let Some(first) = animation.frames.first() {
+        let control = &first.control;
+        if control.x_offset != 0
+            || control.y_offset != 0
+            || control.width != ihdr.width()
+            || control.height != ihdr.height()
+        {
+            problems.push("the first frame must cover the whole canvas".to_string());
+        }
+    }
+    Ok(problems)
+}
+
+/// Parses a frame delay such as `100ms`, `1.5s` or `1/30` into the
+/// numerator and denominator stored in fcTL.
+pub fn parse_delay(delay: &str) -> Result<(u16, u16), &'static str> {
+    const INVALID: &str = "delays look like 100ms, 1.5s or 1/30";
+    if let Some((num, den)) = delay.split_once('/') {
+        let num = num.trim().parse().map_err(|_| INVALID)?;
+        let den = den.trim().parse().map_err(|_| INVALID)?;
+        return match den {
+            0 => Err("the delay denominator must be non-zero"),
+            den => Ok((num, den)),
+        };
+    }
+
+    let millis = match delay.strip_suffix("ms") {
+        Some(millis) => millis.trim().parse::<f64>().map_err(|_| INVALID)?,
+        None => {
+            let seconds = delay.strip_suffix('s').ok_or(INVALID)?;
+            seconds.trim().parse::<f64>().map_err(|_| INVALID)? * 1000.0
+        }
+    };
+    if !(0.0..=u16::MAX as f64).contains(&millis) {
+        return Err("delays must be between 0 and 65535 ms");
+    }
+    Ok((millis.round() as u16, 1000))
+}
+
+/// Builds an APNG from still images that share the same IHDR. The first
+/// frame is also the IDAT image shown by viewers without APNG support; its
+/// ancillary chunks, such as PLTE and gAMA, are kept.
+pub fn build(frames: &[Png], delay: (u16, u16), num_plays: u32) -> crate::Result<Png> {
+    let first = frames
+        .first()
+        .ok_or("an animation needs at least one frame")?;
+    let ihdr = first.ihdr()?;
+    for (i, frame) in frames.iter().enumerate().skip(1) {
+        let frame_ihdr = frame.ihdr()?;
+        if (frame_ihdr.width(), frame_ihdr.height()) != (ihdr.width(), ihdr.height()) {
+            return Err(format!(
+                "frame {i} is {}x{}, expected {}x{}",
+                frame_ihdr.width(),
+                frame_ihdr.height(),
+                ihdr.width(),
+                ihdr.height()
+            )
+            .into());
+        }
+        if frame_ihdr != ihdr {
+            return Err(format!(
+                "frame {i} is {} at {} bits, expected {} at {} bits",
+                frame_ihdr.color_type(),
+                frame_ihdr.bit_depth(),
+                ihdr.color_type(),
+                ihdr.bit_depth()
+            )
+            .into());
+        }
+        if frame.chunk_by_type("PLTE").map(|c| c.data().to_vec())
+            != first.chunk_by_type("PLTE").map(|c| c.data().to_vec())
+        {
+            return Err(format!("frame {i} has a different palette").into());
+        }
+    }
+
+    let control = |sequence_number| FrameControl {
+        sequence_number,
+        width: ihdr.width(),
+        height: ihdr.height(),
+        x_offset: 0,
+        y_offset: 0,
+        delay_num: delay.0,
+        delay_den: delay.1,
+        dispose_op: DisposeOp::None,
+        blend_op: BlendOp::Source,
+    };
+    let animation_control = AnimationControl {
+        num_frames: frames.len() as u32,
+        num_plays,
+    };
+
+    let mut chunks = Vec::new();
+    for chunk in first.chunks().into_iter().take_while(is_before_image) {
+        let chunk_type = chunk.chunk_type().to_string();
+        if ANIMATION_CHUNKS.contains(&&chunk_type[..]) {
+            continue;
+        }
+        chunks.push(chunk);
+        if chunk_type == "IHDR" {
+            chunks.push(animation_control.to_chunk());
+        }
+    }
+
+    let mut sequence_number = 0;
+    chunks.push(control(sequence_number).to_chunk());
+    sequence_number += 1;
+    chunks.extend(
+        first
+            .chunks()
+            .into_iter()
+            .filter(|c| c.chunk_type().to_string() == "IDAT"),
+    );
+
+    for frame in frames.iter().skip(1) {
+        chunks.push(control(sequence_number).to_chunk());
+        sequence_number += 1;
+        for idat in frame
+            .chunks()
+            .iter()
+            .filter(|c| c.chunk_type().to_string() == "IDAT")
+        {
+            chunks.push(
+                FrameData {
+                    sequence_number,
+                    data: idat.data().to_vec(),
+                }
+                .to_chunk(),
+            );
+            sequence_number += 1;
+        }
+    }
+
+    chunks.push(Chunk::new(ChunkType::from_str("IEND")?, Vec::new()));
+    Ok(Png::from_chunks(chunks))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::Image;
+    use crate::encoder::Encoder;
+    use crate::ihdr::ColorType;
+
+    fn control(sequence_number: u32, width: u32, height: u32) -> FrameControl {
+        FrameControl {
+            sequence_number,
+            width,
+            height,
+            x_offset: 0,
+            y_offset: 0,
+            delay_num: 1,
+            delay_den: 10,
+            dispose_op: DisposeOp::None,
+            blend_op: BlendOp::Source,
+        }
+    }
+
+    /// A 4x4 grey animation: the IDAT image, then a 2x2 frame.
+    fn testing_apng() -> Png {
+        let still = Encoder::new(Ihdr::new(4, 4, ColorType::Grayscale, 8).unwrap())
+            .encode(&[10; 16])
+            .unwrap();
+        let small = Encoder::new(Ihdr::new(2, 2, ColorType::Grayscale, 8).unwrap())
+            .encode(&[200; 4])
+            .unwrap();
+
+        let mut chunks = vec![
+            still.chunks()[0].clone(),
+            AnimationControl {
+                num_frames: 2,
+                num_plays: 0,
+            }
+            .to_chunk(),
+            control(0, 4, 4).to_chunk(),
+        ];
+        chunks.extend(encoder::split_idat(&still.idat_data(), 8));
+        chunks.push(control(1, 2, 2).to_chunk());
+        chunks.push(
+            FrameData {
+                sequence_number: 2,
+                data: small.idat_data(),
+            }
+            .to_chunk(),
+        );
+        chunks.push(Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()));
+        Png::from_chunks(chunks)
+    }
+
+    #[test]
+    fn test_frame_control_round_trip() {
+        let mut fctl = control(7, 30, 20);
+        fctl.dispose_op = DisposeOp::Previous;
+        fctl.blend_op = BlendOp::Over;
+        let parsed = FrameControl::try_from(&fctl.as_bytes()[..]).unwrap();
+        assert_eq!(parsed, fctl);
+        assert_eq!(parsed.delay(), Duration::from_millis(100));
+
+        let mut bytes = fctl.as_bytes();
+        bytes[24] = 3;
+        assert!(FrameControl::try_from(&bytes[..]).is_err());
+    }
+
+    #[test]
+    fn test_animation() {
+        let png = testing_apng();
+        let animation = Animation::try_from(&png).unwrap();
+        assert_eq!(animation.control.num_frames, 2);
+        assert_eq!(animation.frames.len(), 2);
+        assert!(animation.has_default_frame());
+        assert_eq!(animation.frames[0].data, png.idat_data());
+        assert!(!animation.frames[1].is_default_image);
+        assert_eq!(animation.duration(), Duration::from_millis(200));
+        assert!(validate(&png).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_validate_sequence_numbers() {
+        let mut chunks = testing_apng().chunks();
+        let fdat = chunks.len() - 2;
+        chunks[fdat] = FrameData {
+            sequence_number: 5,
+            data: FrameData::try_from(chunks[fdat].data()).unwrap().data,
+        }
+        .to_chunk();
+
+        let problems = validate(&Png::from_chunks(chunks)).unwrap();
+        assert_eq!(problems, vec!["fdAT has sequence number 5, expected 2"]);
+    }
+
+    #[test]
+    fn test_parse_delay() {
+        assert_eq!(parse_delay("100ms"), Ok((100, 1000)));
+        assert_eq!(parse_delay("1.5s"), Ok((1500, 1000)));
+        assert_eq!(parse_delay("1/30"), Ok((1, 30)));
+        assert!(parse_delay("100").is_err());
+        assert!(parse_delay("1/0").is_err());
+        assert!(parse_delay("70s").is_err());
+    }
+
+    #[test]
+    fn test_build() {
+        let ihdr = Ihdr::new(3, 2, ColorType::Grayscale, 8).unwrap();
+        let frames: Vec<Png> = (0..3)
+            .map(|i| {
+                Encoder::new(ihdr.clone())
+                    .with_idat_size(4)
+                    .encode(&[i * 50; 6])
+                    .unwrap()
+            })
+            .collect();
+
+        let png = build(&frames, (1, 10), 2).unwrap();
+        assert!(validate(&png).unwrap().is_empty());
+        let animation = Animation::try_from(&png).unwrap();
+        assert_eq!(animation.control.num_plays, 2);
+        assert_eq!(animation.frames.len(), 3);
+        assert!(animation.has_default_frame());
+
+        let last = Image::try_from(&animation.frame_png(&png, 2).unwrap()).unwrap();
+        assert_eq!(last.pixels(), &[100; 6]);
+
+        let other = Encoder::new(Ihdr::new(2, 2, ColorType::Grayscale, 8).unwrap())
+            .encode(&[0; 4])
+            .unwrap();
+        let mixed = [Png::from_chunks(frames[0].chunks()), other];
+        assert!(build(&mixed, (1, 10), 0).is_err());
+    }
+
+    #[test]
+    fn test_frame_png() {
+        let png = testing_apng();
+        let animation = Animation::try_from(&png).unwrap();
+
+        let frame = Image::try_from(&animation.frame_png(&png, 1).unwrap()).unwrap();
+        assert_eq!(frame.ihdr().width(), 2);
+        assert_eq!(frame.pixels(), &[200; 4]);
+        assert!(animation.frame_png(&png, 2).is_err());
+    }
+}
@@ -0,0 +1,178 @@
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+/// Marks a payload as an embedded file rather than a text message.
+pub const MAGIC: [u8; 4] = *b"pmF\x01";
+
+const CHECKSUM_LEN: usize = 32;
+
+/// An arbitrary file carried as a message: its bytes plus enough metadata to
+/// restore it under its original name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attachment {
+    name: String,
+    mime: String,
+    data: Vec<u8>,
+}
+
+impl TryFrom<&[u8]> for Attachment {
+    type Error = crate::Error;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        if !is_attachment(value) {
+            return Err("not an embedded file".into());
+        }
+        let truncated = || -> crate::Error { "truncated file header".into() };
+
+        let mut at = MAGIC.len();
+        let name_len = u16::from_be_bytes(
+            value
+                .get(at..at + 2)
+                .ok_or_else(truncated)?
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        at += 2;
+        let name = String::from_utf8(value.get(at..at + name_len).ok_or_else(truncated)?.to_vec())?;
+        at += name_len;
+
+        let mime_len = *value.get(at).ok_or_else(truncated)? as usize;
+        at += 1;
+        let mime = String::from_utf8(value.get(at..at + mime_len).ok_or_else(truncated)?.to_vec())?;
+        at += mime_len;
+
+        let size = u64::from_be_bytes(
+            value
+                .get(at..at + 8)
+                .ok_or_else(truncated)?
+                .try_into()
+                .unwrap(),
+        );
+        at += 8;
+        let checksum = value.get(at..at + CHECKSUM_LEN).ok_or_else(truncated)?;
+        at += CHECKSUM_LEN;
+
+        let data = &value[at..];
+        if data.len() as u64 != size {
+            return Err(format!(
+                "embedded file should be {size} bytes but {} were found",
+                data.len()
+            )
+            .into());
+        }
+        if Sha256::digest(data)[..] != checksum[..] {
+            return Err("embedded file checksum mismatch".into());
+        }
+
+        Ok(Attachment {
+            name,
+            mime,
+            data: data.to_vec(),
+        })
+    }
+}
+
+impl Attachment {
+    pub fn new(name: &str, mime: &str, data: Vec<u8>) -> crate::Result<Attachment> {
+        if name.len() > u16::MAX as usize || mime.len() > u8::MAX as usize {
+            return Err("file name or MIME type too long".into());
+        }
+        Ok(Attachment {
+            name: name.to_string(),
+            mime: mime.to_string(),
+            data,
+        })
+    }
+
+    /// Reads `path`, keeping only its file name and guessing the MIME type
+    /// from the extension.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> crate::Result<Attachment> {
+        let path = path.as_ref();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or("file name is not valid UTF-8")?;
+        Attachment::new(name, guess_mime(path), std::fs::read(path)?)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn mime(&self) -> &str {
+        &self.mime
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend((self.name.len() as u16).to_be_bytes());
+        bytes.extend(self.name.as_bytes());
+        bytes.push(self.mime.len() as u8);
+        bytes.extend(self.mime.as_bytes());
+        bytes.extend((self.data.len() as u64).to_be_bytes());
+        bytes.extend(Sha256::digest(&self.data));
+        bytes.extend(self.data.iter());
+        bytes
+    }
+}
+
+pub fn is_attachment(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+fn guess_mime(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("txt") => "text/plain",
+        Some("json") => "application/json",
+        Some("pdf") => "application/pdf",
+        Some("zip") => "application/zip",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("pem" | "asc") => "application/pgp-keys",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attachment_round_trip() {
+        let data: Vec<u8> = (0..=255).collect();
+        let attachment = Attachment::new("key.bin", "application/octet-stream", data).unwrap();
+        let bytes = attachment.as_bytes();
+
+        assert!(is_attachment(&bytes));
+        assert_eq!(Attachment::try_from(&bytes[..]).unwrap(), attachment);
+    }
+
+    #[test]
+    fn test_attachment_detects_corruption() {
+        let attachment = Attachment::new("a.pdf", "application/pdf", vec![1, 2, 3]).unwrap();
+        let mut bytes = attachment.as_bytes();
+
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        assert!(Attachment::try_from(&bytes[..]).is_err());
+
+        bytes.pop();
+        assert!(Attachment::try_from(&bytes[..]).is_err());
+    }
+
+    #[test]
+    fn test_guess_mime() {
+        assert_eq!(guess_mime(Path::new("report.PDF")), "application/pdf");
+        assert_eq!(guess_mime(Path::new("noext")), "application/octet-stream");
+    }
+}
@@ -6,23 +6,12 @@ use std::{
 
 use crc::{Crc, CRC_32_ISO_HDLC};
 
-use crate::{chunk_type, Error};
+use crate::Error;
 
 use super::chunk_type::ChunkType;
 
-#[derive(Debug, Clone)]
-struct ChunkByteError;
-
-impl std::error::Error for ChunkByteError {}
-
 pub const CRC_PNG: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
 
-impl Display for ChunkByteError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "invalid bytes")
-    }
-}
-
 #[derive(Clone)]
 pub struct Chunk {
     length: u32,
@@ -36,42 +25,31 @@ impl TryFrom<&[u8]> for Chunk {
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
         let mut reader = BufReader::new(value);
-        let mut length = 0;
 
         let mut length_bytes: [u8; 4] = [0; 4];
-        let mut read_result = reader.read_exact(&mut length_bytes);
-
-        match read_result {
-            Ok(_) => {
-                length = u32::from_be_bytes(length_bytes);
-                let mut chunk_type_bytes: [u8; 4] = [0; 4];
-                read_result = reader.read_exact(&mut chunk_type_bytes);
-                match read_result {
-                    Ok(_) => {
-                        let chunk_type = ChunkType::try_from(chunk_type_bytes).unwrap();
-                        let ulen: u8 = length as u8;
-                        let mut data_vec: Vec<_> = (0..ulen).collect();
-                        read_result = reader.read_exact(&mut data_vec[..]);
-                        match read_result {
-                            Ok(_) => {
-                                let mut crc_bytes: [u8; 4] = [0; 4];
-                                read_result = reader.read_exact(&mut crc_bytes);
-                                match read_result {
-                                    Ok(_) => {
-                                        let chunk: Chunk = Chunk::new(chunk_type, data_vec);
-                                        return Ok(chunk);
-                                    }
-                                    Err(e) => return Err(Box::new(e)),
-                                };
-                            }
-                            Err(e) => return Err(Box::new(e)),
-                        }
-                    }
-                    Err(e) => return Err(Box::new(e)),
-                };
-            }
-            Err(e) => return Err(Box::new(e)),
-        };
+        reader.read_exact(&mut length_bytes)?;
+        let length = u32::from_be_bytes(length_bytes);
+
+        let mut chunk_type_bytes: [u8; 4] = [0; 4];
+        reader.read_exact(&mut chunk_type_bytes)?;
+        let chunk_type = ChunkType::try_from(chunk_type_bytes)?;
+
+        if length > Chunk::MAX_LENGTH || length as usize + 12 > value.len() {
+            return Err(format!("invalid chunk length {length}").into());
+        }
+        let mut data_vec: Vec<u8> = vec![0; length as usize];
+        reader.read_exact(&mut data_vec[..])?;
+
+        let mut crc_bytes: [u8; 4] = [0; 4];
+        reader.read_exact(&mut crc_bytes)?;
+        let crc = u32::from_be_bytes(crc_bytes);
+
+        let chunk: Chunk = Chunk::new(chunk_type, data_vec);
+        if chunk.crc() != crc {
+            return Err(format!("crc mismatch in {} chunk", chunk.chunk_type()).into());
+        }
+
+        Ok(chunk)
     }
 }
 
@@ -88,22 +66,23 @@ impl Display for Chunk {
 }
 
 impl Chunk {
-    pub fn new(chunk_type: ChunkType, data: Vec<u8>) -> Chunk {
-        let len = data.len() as u32;
+    /// The largest chunk length the PNG specification allows, 2^31 - 1.
+    pub const MAX_LENGTH: u32 = (1 << 31) - 1;
 
+    pub fn new(chunk_type: ChunkType, data: Vec<u8>) -> Chunk {
         Chunk {
-            length: len,
-            chunk_data: data.clone(),
-            crc: make_crc(len, &chunk_type, data),
-            chunk_type: chunk_type,
+            length: data.len() as u32,
+            crc: make_crc(&chunk_type, &data),
+            chunk_data: data,
+            chunk_type,
         }
     }
 
-    fn length(&self) -> u32 {
+    pub fn length(&self) -> u32 {
         self.length
     }
 
-    fn crc(&self) -> u32 {
+    pub fn crc(&self) -> u32 {
         self.crc
     }
 
@@ -111,7 +90,7 @@ impl Chunk {
         &self.chunk_type
     }
 
-    fn data(&self) -> &[u8] {
+    pub fn data(&self) -> &[u8] {
         &self.chunk_data[..]
     }
 
@@ -119,19 +98,13 @@ impl Chunk {
         type_string: String,
         data_string: String,
     ) -> Result<Chunk, &'static str> {
-        let type_result = ChunkType::from_str(&type_string[..]);
+        let chunk_type = ChunkType::from_str(&type_string[..])?;
 
-        match type_result {
-            Ok(chunk_type) => return Ok(Chunk::new(chunk_type, data_string.as_bytes().to_vec())),
-            Err(e) => return Err(e),
-        }
+        Ok(Chunk::new(chunk_type, data_string.into_bytes()))
     }
 
     pub fn data_as_string(&self) -> Result<String, Utf8Error> {
-        match std::str::from_utf8(self.data()) {
-            Ok(s) => Ok(s.to_string()),
-            Err(e) => Err(e),
-        }
+        std::str::from_utf8(self.data()).map(|s| s.to_string())
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
@@ -158,24 +131,13 @@ impl Chunk {
     }
 }
 
-fn make_crc(length: u32, chunk_type: &ChunkType, data: Vec<u8>) -> u32 {
-    let length_as_bytes = length.to_be_bytes();
+pub fn make_crc(chunk_type: &ChunkType, data: &[u8]) -> u32 {
     let type_bytes: [u8; 4] = chunk_type.bytes();
-    let data_bytes: &[u8] = &data[..];
-
-    let mut chunk_vec: Vec<u8> = Vec::new();
 
-    for byte in length_as_bytes.iter() {
-        chunk_vec.push(*byte);
-    }
-    for byte in type_bytes.iter() {
-        chunk_vec.push(*byte);
-    }
-    for byte in data_bytes.iter() {
-        chunk_vec.push(*byte);
-    }
-
-    CRC_PNG.checksum(&chunk_vec[..])
+    let mut digest = CRC_PNG.digest();
+    digest.update(&type_bytes);
+    digest.update(data);
+    digest.finalize()
 }
 
 #[cfg(test)]
@@ -209,9 +171,9 @@ mod tests {
             .as_bytes()
             .to_vec();
         let chunk = Chunk::new(chunk_type, data);
-        println!("{}", format!("{}", chunk));
+        println!("{}", chunk);
         assert_eq!(chunk.length(), 42);
-        //assert_eq!(chunk.crc(), 2882656334);
+        assert_eq!(chunk.crc(), 2882656334);
     }
 
     #[test]
@@ -237,7 +199,7 @@ mod tests {
     #[test]
     fn test_chunk_crc() {
         let chunk = testing_chunk();
-        // assert_eq!(chunk.crc(), 2882656334);
+        assert_eq!(chunk.crc(), 2882656334);
     }
 
     #[test]
@@ -258,7 +220,7 @@ mod tests {
 
         let chunk = Chunk::try_from(chunk_data.as_ref()).unwrap();
 
-        println!("{}", format!("{}", chunk));
+        println!("{}", chunk);
 
         let chunk_string = chunk.data_as_string().unwrap();
         let expected_chunk_string = String::from("This is where your secret message will be!");
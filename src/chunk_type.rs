@@ -35,9 +35,9 @@ impl FromStr for ChunkType {
                     return Err("invalid string");
                 }
             }
-            return Ok(ChunkType { bytes: chunk_bytes });
+            Ok(ChunkType { bytes: chunk_bytes })
         } else {
-            return Err("invalid string");
+            Err("invalid string")
         }
     }
 }
@@ -66,6 +66,32 @@ impl ChunkType {
         self.bytes
     }
 
+    /// An ancillary, private, safe-to-copy type with a valid reserved bit,
+    /// the only kind that suits custom payloads. The letters of `seed` are
+    /// kept where possible, so `rust` and `RUST` both give `ruSt`; missing
+    /// letters are derived from a hash of the seed.
+    pub fn private_ancillary(seed: &str) -> ChunkType {
+        // FNV-1a, so the same seed gives the same type on every platform.
+        let mut hash = seed.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+        let mut letters = seed.bytes().filter(u8::is_ascii_alphabetic);
+        let mut bytes = [0; 4];
+        for byte in bytes.iter_mut() {
+            *byte = letters.next().unwrap_or_else(|| {
+                let letter = b'a' + (hash % 26) as u8;
+                hash /= 26;
+                letter
+            });
+        }
+
+        bytes[0].make_ascii_lowercase();
+        bytes[1].make_ascii_lowercase();
+        bytes[2].make_ascii_uppercase();
+        bytes[3].make_ascii_lowercase();
+        ChunkType { bytes }
+    }
+
     fn is_valid_byte(byte: u8) -> bool {
         if (byte > 64 && byte < 91) || (byte > 96 && byte < 123) {
             return true;
@@ -73,7 +99,8 @@ impl ChunkType {
         false
     }
 
-    fn is_valid(&self) -> bool {
+    /// Four ASCII letters with the reserved bit clear.
+    pub fn is_valid(&self) -> bool {
         for byte in self.bytes {
             if !ChunkType::is_valid_byte(byte) {
                 return false;
@@ -95,19 +122,23 @@ impl ChunkType {
         false
     }
 
-    fn is_critical(&self) -> bool {
+    /// Decoders must understand critical chunks to display the image.
+    pub fn is_critical(&self) -> bool {
         self.i_is_uppercase(0)
     }
 
-    fn is_public(&self) -> bool {
+    /// Public chunks are registered; private ones are application specific.
+    pub fn is_public(&self) -> bool {
         self.i_is_uppercase(1)
     }
 
-    fn is_reserved_bit_valid(&self) -> bool {
+    pub fn is_reserved_bit_valid(&self) -> bool {
         self.i_is_uppercase(2)
     }
 
-    fn is_safe_to_copy(&self) -> bool {
+    /// Whether editors that change critical chunks may keep the chunk
+    /// without understanding it.
+    pub fn is_safe_to_copy(&self) -> bool {
         !self.i_is_uppercase(3)
     }
 }
@@ -195,6 +226,25 @@ mod tests {
         let chunk = ChunkType::from_str("Ru1t");
         assert!(chunk.is_err());
     }
+    #[test]
+    pub fn test_private_ancillary() {
+        assert_eq!(ChunkType::private_ancillary("RUST").to_string(), "ruSt");
+        assert_eq!(ChunkType::private_ancillary("my app!").to_string(), "myAp");
+
+        for seed in ["", "x", "12345", "a much longer seed"] {
+            let chunk_type = ChunkType::private_ancillary(seed);
+            assert!(chunk_type.is_valid());
+            assert!(!chunk_type.is_critical());
+            assert!(!chunk_type.is_public());
+            assert!(chunk_type.is_safe_to_copy());
+            assert_eq!(chunk_type, ChunkType::private_ancillary(seed));
+        }
+        assert_ne!(
+            ChunkType::private_ancillary("1"),
+            ChunkType::private_ancillary("2")
+        );
+    }
+
     #[test]
     pub fn test_chunk_type_string() {
         let chunk = ChunkType::from_str("RuSt").unwrap();
@@ -1,9 +1,29 @@
+use crate::apng::{self, Animation};
+use crate::attachment::{self, Attachment};
 use crate::chunk::Chunk;
 use crate::chunk_type::ChunkType;
-use crate::png::Png;
-use clap::{Parser, Subcommand};
+use crate::compress;
+use crate::crypto::{self, KdfParams, Secret};
+use crate::decoder::Image;
+use crate::detect;
+use crate::encoder::replace_image;
+use crate::fec;
+use crate::lsb::{self, ChannelMask, LsbOptions};
+use crate::message::{self, Message, Selector};
+use crate::mng;
+use crate::optimize::{self, optimize, OptimizeOptions};
+use crate::png::{Format, Png};
+use crate::recover;
+use crate::registry;
+use crate::signature::{self, CoverageStatus, PngSignature};
+use crate::split::{self, Part};
+use crate::strip::{self, ChunkClass, Rule};
+use crate::Result;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::io::Write;
 use std::path::Path;
 use std::str::FromStr;
+use x25519_dalek::PublicKey;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -18,24 +38,369 @@ enum Commands {
     Encode {
         file_path: String,
         chunk_type: String,
-        message: String,
+        /// Text to hide; with --file this is the output file instead
+        #[arg(required_unless_present = "file")]
+        message: Option<String>,
         output_file: Option<String>,
+        #[command(flatten)]
+        payload: PayloadArgs,
+        #[command(flatten)]
+        method: MethodArgs,
+        #[command(flatten)]
+        secret: SecretArgs,
     },
     Decode {
         file_path: String,
         chunk_type: String,
+        /// Write the message to this file; embedded files default to their
+        /// original name
+        #[arg(long)]
+        output: Option<String>,
+        #[command(flatten)]
+        select: SelectArgs,
+        #[command(flatten)]
+        method: MethodArgs,
+        #[command(flatten)]
+        secret: SecretArgs,
     },
     Remove {
         file_path: String,
         chunk_type: String,
+        #[command(flatten)]
+        select: SelectArgs,
+    },
+    /// List the hidden messages stored in chunks
+    List {
+        file_path: String,
     },
     Print {
         file_path: String,
     },
+    /// Summarize the chunk types of an image and check them against the spec
+    Info {
+        file_path: String,
+    },
+    /// Losslessly recompress the image data, keeping hidden messages
+    Optimize {
+        file_path: String,
+        output_file: Option<String>,
+        /// Ancillary chunk type to remove, may be repeated
+        #[arg(long)]
+        strip: Vec<String>,
+        /// Keep the original colour type and bit depth; needed to keep a
+        /// message hidden in the pixels with a key
+        #[arg(long)]
+        no_reduce: bool,
+        /// Keep unknown unsafe-to-copy chunks that the spec says to drop
+        #[arg(long)]
+        keep_unsafe: bool,
+    },
+    /// Merge or split the IDAT chunks without recompressing them
+    Rechunk {
+        file_path: String,
+        output_file: Option<String>,
+        /// Largest IDAT chunk to write; a single IDAT when omitted
+        #[arg(long)]
+        max_len: Option<usize>,
+    },
+    /// Create an X25519 identity for public-key encrypted messages
+    Keygen {
+        /// Where to write the secret key; the public key goes to <file>.pub
+        identity_file: String,
+        /// Create an Ed25519 signing key for `sign` instead
+        #[arg(long)]
+        signing: bool,
+    },
+    /// Sign the critical chunks, and any included ancillary ones, with Ed25519
+    Sign {
+        file_path: String,
+        output_file: Option<String>,
+        /// Signing key written by `keygen --signing`
+        #[arg(long)]
+        key: String,
+        /// Ancillary chunk type to cover as well, may be repeated
+        #[arg(long)]
+        include: Vec<String>,
+        /// Write the signature to this file instead of embedding it
+        #[arg(long)]
+        detached: Option<String>,
+    },
+    /// Scan images for signs of hidden data
+    Detect {
+        #[arg(required = true)]
+        files: Vec<String>,
+    },
+    /// Check a signature and report which covered chunks changed
+    Verify {
+        file_path: String,
+        /// Trusted public key (hex, or a .pub file)
+        #[arg(long, required = true)]
+        key: String,
+        /// Detached signature written by `sign --detached`
+        #[arg(long)]
+        signature: Option<String>,
+    },
+    /// Show, extract or strip the data appended after IEND
+    Trailing {
+        file_path: String,
+        /// Where to write the stripped image; the input file when omitted
+        output_file: Option<String>,
+        /// Write the trailing data to this file
+        #[arg(long)]
+        extract: Option<String>,
+        /// Remove the trailing data from the image
+        #[arg(long)]
+        strip: bool,
+    },
+    /// Remove metadata chunks from images, in place
+    Strip {
+        #[arg(required = true)]
+        files: Vec<String>,
+        /// Ancillary chunk type, `text` or `private` to keep, removing all
+        /// other ancillary chunks; may be repeated. Defaults to the chunks
+        /// that affect how the image is displayed
+        #[arg(long, conflicts_with = "remove", value_parser = ChunkClass::from_str)]
+        keep: Vec<ChunkClass>,
+        /// Ancillary chunk type, `text` or `private` to remove, keeping all
+        /// other chunks; may be repeated
+        #[arg(long, value_parser = ChunkClass::from_str)]
+        remove: Vec<ChunkClass>,
+        /// Only report what would be removed
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Salvage the intact chunks of a damaged file
+    Repair {
+        file_path: String,
+        output_file: String,
+        /// Keep chunks whose CRC is wrong, with a corrected CRC
+        #[arg(long)]
+        keep_bad_crc: bool,
+    },
+    /// Rewrite chunk CRCs that disagree with the chunk data
+    FixCrc {
+        file_path: String,
+        output_file: Option<String>,
+        /// Only list the chunks that would change
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Write the PNG and JPEG images embedded in an MNG or JNG file
+    ExtractImages {
+        file_path: String,
+        /// Directory to write the images to
+        #[arg(default_value = ".")]
+        output_dir: String,
+    },
+    /// Pick chunk types suited to custom payloads
+    ChunkType {
+        #[command(subcommand)]
+        command: ChunkTypeCommands,
+    },
+    /// Inspect animated PNGs
+    Apng {
+        #[command(subcommand)]
+        command: ApngCommands,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ChunkTypeCommands {
+    /// Generate an ancillary, private, safe-to-copy type
+    Suggest {
+        /// Letters to build the type from; random when omitted
+        seed: Option<String>,
+    },
+    /// Explain whether a type is suited to custom payloads
+    Check { chunk_type: String },
+}
+
+#[derive(Subcommand, Debug)]
+enum ApngCommands {
+    /// List the frames with their delays and disposal ops
+    Info { file_path: String },
+    /// Write frames as standalone PNGs named frame_000.png, frame_001.png...
+    Extract {
+        file_path: String,
+        /// Directory to write the frames to
+        #[arg(default_value = ".")]
+        output_dir: String,
+        /// Only extract this frame, counting from 0
+        #[arg(long)]
+        frame: Option<usize>,
+    },
+    /// Build an animation from PNG frames of the same size and colour type
+    Build {
+        #[arg(required = true)]
+        frames: Vec<String>,
+        #[arg(short, long, default_value = "animation.png")]
+        output: String,
+        /// Time each frame is shown, such as 100ms, 1.5s or 1/30
+        #[arg(long, default_value = "100ms", value_parser = apng::parse_delay)]
+        delay: (u16, u16),
+        /// Times to play the animation; 0 loops forever
+        #[arg(long, default_value_t = 0)]
+        loops: u32,
+    },
+}
+
+/// Why `chunk_type` is a poor home for a custom payload.
+fn chunk_type_problems(chunk_type: &ChunkType) -> Vec<&'static str> {
+    let mut problems = Vec::new();
+    if chunk_type.is_critical() {
+        problems.push("is critical, so viewers will reject the image");
+    }
+    if chunk_type.is_public() {
+        problems.push("is public, a name reserved for registered chunks");
+    }
+    if !chunk_type.is_reserved_bit_valid() {
+        problems.push("needs an uppercase third letter");
+    }
+    if !chunk_type.is_safe_to_copy() {
+        problems.push("is unsafe to copy, so editors drop it");
+    }
+    if registry::lookup(chunk_type).is_some() {
+        problems.push("is a registered chunk type");
+    }
+    problems
+}
+
+#[derive(Args, Clone, Debug)]
+struct PayloadArgs {
+    /// Hide the contents of this file instead of a text message
+    #[arg(long)]
+    file: Option<String>,
+    /// Name the message so others of the same chunk type can coexist
+    #[arg(long)]
+    name: Option<String>,
+    /// Deflate the message before encrypting and embedding it
+    #[arg(long)]
+    compress: bool,
+}
+
+#[derive(Args, Clone, Debug)]
+struct SelectArgs {
+    /// Act on the message with this name
+    #[arg(long, conflicts_with_all = ["index", "all"])]
+    name: Option<String>,
+    /// Act on the message at this position among those of the chunk type,
+    /// counting from 0
+    #[arg(long, conflicts_with = "all")]
+    index: Option<usize>,
+    /// Act on every message of the chunk type
+    #[arg(long)]
+    all: bool,
+}
+
+impl SelectArgs {
+    fn selector(&self) -> Selector {
+        match (&self.name, self.index, self.all) {
+            (Some(name), _, _) => Selector::Name(name.clone()),
+            (None, Some(index), _) => Selector::Index(index),
+            (None, None, true) => Selector::All,
+            (None, None, false) => Selector::First,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Method {
+    /// Store the message in its own chunk
+    Chunk,
+    /// Hide the message in the low bits of the pixels
+    Lsb,
+}
+
+#[derive(Args, Clone, Debug)]
+struct MethodArgs {
+    /// Where the message is stored
+    #[arg(long, value_enum, default_value_t = Method::Chunk)]
+    method: Method,
+    /// Low bits of each sample used by --method lsb
+    #[arg(long, default_value_t = 1)]
+    bits: u8,
+    /// Samples used by --method lsb, any of r, g, b and a
+    #[arg(long, default_value = "rgb")]
+    channels: String,
+    /// Passphrase scattering --method lsb bits over the image
+    #[arg(long)]
+    key: Option<String>,
+    /// Split larger --method chunk payloads into numbered chunks of at most
+    /// this many bytes
+    #[arg(long, default_value_t = split::DEFAULT_PART_SIZE)]
+    part_size: usize,
+    /// Add Reed–Solomon parity bytes per 255-byte block, repairing up to
+    /// half as many damaged bytes
+    #[arg(long, num_args = 0..=1, default_missing_value = "16")]
+    fec: Option<u8>,
+    /// Keep unknown unsafe-to-copy chunks when --method lsb rewrites the
+    /// image data
+    #[arg(long)]
+    keep_unsafe: bool,
+}
+
+impl MethodArgs {
+    fn lsb_options(&self) -> Result<LsbOptions> {
+        Ok(LsbOptions {
+            bits: self.bits,
+            channels: ChannelMask::from_str(&self.channels)?,
+            key: self.key.clone(),
+        })
+    }
+}
+
+#[derive(Args, Clone, Debug)]
+struct SecretArgs {
+    /// Encrypt or decrypt the message with a passphrase
+    #[arg(long, conflicts_with_all = ["key_file", "recipient", "identity"])]
+    password: Option<String>,
+    /// Encrypt or decrypt the message with the contents of a key file
+    #[arg(long, conflicts_with_all = ["recipient", "identity"])]
+    key_file: Option<String>,
+    /// Encrypt to a public key (hex, or a .pub file), may be repeated
+    #[arg(long)]
+    recipient: Vec<String>,
+    /// Decrypt with the identity file written by `keygen`
+    #[arg(long)]
+    identity: Option<String>,
+}
+
+impl SecretArgs {
+    fn secret(&self) -> Result<Option<Secret>> {
+        match (&self.password, &self.key_file, &self.identity) {
+            (Some(password), _, _) => Ok(Some(Secret::Password(password.clone()))),
+            (None, Some(path), _) => Ok(Some(Secret::Key(std::fs::read(path)?))),
+            (None, None, Some(path)) => Ok(Some(Secret::Identity(crypto::identity_from_hex(
+                &std::fs::read_to_string(path)?,
+            )?))),
+            (None, None, None) => Ok(None),
+        }
+    }
+
+    fn recipients(&self) -> Result<Vec<PublicKey>> {
+        self.recipient
+            .iter()
+            .map(|recipient| match crypto::public_key_from_hex(recipient) {
+                Ok(key) => Ok(key),
+                Err(_) => crypto::public_key_from_hex(&std::fs::read_to_string(recipient)?),
+            })
+            .collect()
+    }
+
+    /// Encrypts `payload` as requested, or returns it unchanged.
+    fn seal(&self, payload: Vec<u8>) -> Result<Vec<u8>> {
+        if !self.recipient.is_empty() {
+            return crypto::encrypt_to_recipients(&payload, &self.recipients()?);
+        }
+        match self.secret()? {
+            Some(secret) => crypto::encrypt(&payload, &secret, &KdfParams::default()),
+            None => Ok(payload),
+        }
+    }
 }
 
 impl Cli {
-    pub fn run(&self) {
+    pub fn run(&self) -> Result<()> {
         // Use match on the reference to the enum variant
         match &self.command {
             Some(Commands::Encode {
@@ -43,31 +408,141 @@ impl Cli {
                 chunk_type,
                 message,
                 output_file,
-            }) => {
-                Cli::encode(
-                    file_path.clone(),
-                    chunk_type.clone(),
-                    message.clone(),
-                    output_file.clone(),
-                );
-            }
+                payload,
+                method,
+                secret,
+            }) => Cli::encode(
+                file_path.clone(),
+                chunk_type.clone(),
+                message.clone(),
+                output_file.clone(),
+                payload,
+                method,
+                secret,
+            ),
             Some(Commands::Decode {
                 file_path,
                 chunk_type,
-            }) => {
-                Cli::decode(file_path.clone(), chunk_type.clone());
-            }
+                output,
+                select,
+                method,
+                secret,
+            }) => Cli::decode(
+                file_path.clone(),
+                chunk_type.clone(),
+                output.clone(),
+                &select.selector(),
+                method,
+                secret,
+            ),
             Some(Commands::Remove {
                 file_path,
                 chunk_type,
+                select,
+            }) => Cli::remove(file_path.clone(), chunk_type.clone(), &select.selector()),
+            Some(Commands::List { file_path }) => Cli::list(file_path.clone()),
+            Some(Commands::Print { file_path }) => Cli::print_chunks(file_path.clone()),
+            Some(Commands::Info { file_path }) => Cli::info(file_path.clone()),
+            Some(Commands::Optimize {
+                file_path,
+                output_file,
+                strip,
+                no_reduce,
+                keep_unsafe,
+            }) => Cli::optimize(
+                file_path.clone(),
+                output_file.clone(),
+                strip.clone(),
+                *no_reduce,
+                *keep_unsafe,
+            ),
+            Some(Commands::Rechunk {
+                file_path,
+                output_file,
+                max_len,
+            }) => Cli::rechunk(file_path.clone(), output_file.clone(), *max_len),
+            Some(Commands::Keygen {
+                identity_file,
+                signing,
+            }) => Cli::keygen(identity_file.clone(), *signing),
+            Some(Commands::Sign {
+                file_path,
+                output_file,
+                key,
+                include,
+                detached,
+            }) => Cli::sign(
+                file_path.clone(),
+                output_file.clone(),
+                key.clone(),
+                include.clone(),
+                detached.clone(),
+            ),
+            Some(Commands::Verify {
+                file_path,
+                key,
+                signature,
+            }) => Cli::verify(file_path.clone(), key.clone(), signature.clone()),
+            Some(Commands::Detect { files }) => Cli::detect(files),
+            Some(Commands::Trailing {
+                file_path,
+                output_file,
+                extract,
+                strip,
+            }) => Cli::trailing(
+                file_path.clone(),
+                output_file.clone(),
+                extract.clone(),
+                *strip,
+            ),
+            Some(Commands::Strip {
+                files,
+                keep,
+                remove,
+                dry_run,
             }) => {
-                Cli::remove(file_path.clone(), chunk_type.clone());
-            }
-            Some(Commands::Print { file_path }) => {
-                Cli::print_chunks(file_path.clone());
+                let rule = match (keep.is_empty(), remove.is_empty()) {
+                    (false, _) => Rule::Keep(keep.clone()),
+                    (true, false) => Rule::Remove(remove.clone()),
+                    (true, true) => Rule::default(),
+                };
+                Cli::strip(files, &rule, *dry_run)
             }
+            Some(Commands::Repair {
+                file_path,
+                output_file,
+                keep_bad_crc,
+            }) => Cli::repair(file_path.clone(), output_file.clone(), *keep_bad_crc),
+            Some(Commands::FixCrc {
+                file_path,
+                output_file,
+                dry_run,
+            }) => Cli::fix_crc(file_path.clone(), output_file.clone(), *dry_run),
+            Some(Commands::ExtractImages {
+                file_path,
+                output_dir,
+            }) => Cli::extract_images(file_path.clone(), output_dir.clone()),
+            Some(Commands::ChunkType { command }) => match command {
+                ChunkTypeCommands::Suggest { seed } => Cli::suggest_chunk_type(seed.clone()),
+                ChunkTypeCommands::Check { chunk_type } => Cli::check_chunk_type(chunk_type),
+            },
+            Some(Commands::Apng { command }) => match command {
+                ApngCommands::Info { file_path } => Cli::apng_info(file_path.clone()),
+                ApngCommands::Extract {
+                    file_path,
+                    output_dir,
+                    frame,
+                } => Cli::apng_extract(file_path.clone(), output_dir.clone(), *frame),
+                ApngCommands::Build {
+                    frames,
+                    output,
+                    delay,
+                    loops,
+                } => Cli::apng_build(frames, output.clone(), *delay, *loops),
+            },
             None => {
                 println!("No subcommand provided.");
+                Ok(())
             }
         }
     }
@@ -75,66 +550,1006 @@ impl Cli {
     fn encode(
         file_path_str: String,
         chunk_type_str: String,
-        message: String,
+        message: Option<String>,
         output_file_str: Option<String>,
-    ) {
-        let file_path = Path::new(&file_path_str);
-        let chunk_type = ChunkType::from_str(&chunk_type_str[..]).unwrap();
+        payload_args: &PayloadArgs,
+        method: &MethodArgs,
+        secret: &SecretArgs,
+    ) -> Result<()> {
+        let chunk_type = ChunkType::from_str(&chunk_type_str[..])?;
+        let mut png = Png::from_file(&file_path_str)?;
+        let problems = chunk_type_problems(&chunk_type);
+        if method.method == Method::Chunk && !problems.is_empty() {
+            for problem in problems.iter() {
+                println!("warning: {chunk_type} {problem}");
+            }
+            println!(
+                "consider {} instead",
+                ChunkType::private_ancillary(&chunk_type_str)
+            );
+        }
+
+        let (mut plain, output_file_str) = match (payload_args.file.clone(), message) {
+            (Some(path), message) => {
+                if message.is_some() && output_file_str.is_some() {
+                    return Err("--file takes the place of the message argument".into());
+                }
+                let attachment = Attachment::from_file(path)?;
+                println!(
+                    "embedding {} ({}), {} bytes",
+                    attachment.name(),
+                    attachment.mime(),
+                    attachment.data().len()
+                );
+                (attachment.as_bytes(), message.or(output_file_str))
+            }
+            (None, Some(message)) => (Vec::from(message), output_file_str),
+            (None, None) => return Err("no message given".into()),
+        };
+        if payload_args.compress {
+            let compressed = compress::compress(&plain)?;
+            println!("compressed {} -> {} bytes", plain.len(), compressed.len());
+            plain = compressed;
+        }
+        let mut payload = secret.seal(plain)?;
+
+        if let Some(name) = &payload_args.name {
+            let taken = message::messages(&png)
+                .iter()
+                .any(|m| m.chunk_type == chunk_type && m.name.as_deref() == Some(&name[..]));
+            if taken && method.method == Method::Chunk {
+                return Err(format!("a {chunk_type} message named {name} already exists").into());
+            }
+            payload = message::with_name(name, &payload)?;
+        }
+        if let Some(parity) = method.fec {
+            let protected = fec::protect(&payload, parity)?;
+            println!(
+                "error correction: {} -> {} bytes",
+                payload.len(),
+                protected.len()
+            );
+            payload = protected;
+        }
+
+        match method.method {
+            Method::Chunk if payload.len() > method.part_size => {
+                let parts = split::split(&payload, method.part_size)?;
+                println!("split {} bytes into {} chunks", payload.len(), parts.len());
+                for part in parts {
+                    png.append_chunk(Chunk::new(chunk_type.clone(), part.as_bytes()));
+                }
+            }
+            Method::Chunk => png.append_chunk(Chunk::new(chunk_type, payload)),
+            Method::Lsb => {
+                let mut image = Image::try_from(&png)?;
+                let options = method.lsb_options()?;
+                println!(
+                    "capacity: {} bytes, message: {} bytes",
+                    lsb::capacity(&image, &options)?,
+                    payload.len()
+                );
+                lsb::embed(&mut image, &chunk_type, &payload, &options)?;
+                let dropped;
+                (png, dropped) = replace_image(&png, &image.encode()?, method.keep_unsafe)?;
+                for dropped in dropped.iter() {
+                    println!("dropped {}: {}", dropped.chunk.chunk_type(), dropped.reason);
+                }
+            }
+        }
 
-        let file_bytes = std::fs::read(&file_path).unwrap();
-        let vec_message: Vec<u8> = Vec::from(message);
+        let output_file_path = output_file_str.unwrap_or(file_path_str);
+        std::fs::write(Path::new(&output_file_path), png.as_bytes())?;
+        Ok(())
+    }
 
-        let new_chunk = Chunk::new(chunk_type, vec_message);
+    fn decode(
+        file_path_str: String,
+        chunk_type_str: String,
+        output_str: Option<String>,
+        selector: &Selector,
+        method: &MethodArgs,
+        secret: &SecretArgs,
+    ) -> Result<()> {
+        let (png, damage) = message::read_lenient(&std::fs::read(&file_path_str)?)?;
+        for damage in damage.iter() {
+            println!("warning: offset {}: {}", damage.offset, damage.message);
+        }
+        let chunk_type = ChunkType::from_str(&chunk_type_str[..])?;
 
-        let mut png = Png::try_from(&file_bytes[..]).unwrap();
+        let found = match method.method {
+            Method::Chunk => message::find(&png, &chunk_type, selector)?,
+            Method::Lsb => {
+                let image = Image::try_from(&png)?;
+                let data = lsb::extract(&image, &chunk_type, &method.lsb_options()?)?;
+                let (name, payload, corrected) = message::open(&data)?;
+                // The pixels hold a single message, so it is message 0.
+                match selector {
+                    Selector::Name(wanted) if name.as_deref() != Some(&wanted[..]) => {
+                        return Err(format!("no {chunk_type} message named {wanted}").into());
+                    }
+                    Selector::Index(index) if *index > 0 => {
+                        return Err(format!("no {chunk_type} message at index {index}").into());
+                    }
+                    _ => {}
+                }
+                vec![Message {
+                    chunk_type: chunk_type.clone(),
+                    chunks: Vec::new(),
+                    name,
+                    payload: Some(payload),
+                    missing: Vec::new(),
+                    missing_count: 0,
+                    duplicates: Vec::new(),
+                    corrected,
+                }]
+            }
+        };
 
-        png.append_chunk(new_chunk);
+        if found.is_empty() {
+            println!("No {chunk_type_str} chunk found.");
+            return Ok(());
+        }
+        if found.len() > 1 && output_str.is_some() {
+            return Err("--output needs a single message".into());
+        }
 
-        match output_file_str {
-            Some(o) => {
-                let output_file_path = Path::new(&o);
-                let png_bytes = png.as_bytes();
-                std::fs::write(output_file_path, png_bytes).unwrap();
+        // With several messages, one that cannot be read does not stop the
+        // others.
+        let total = found.len();
+        let mut failed = 0;
+        for message in found {
+            if *selector == Selector::All {
+                println!("{}:", message.name.as_deref().unwrap_or("(unnamed)"));
+            }
+            for sequence in message.duplicates.iter() {
+                println!("duplicate part {}", sequence + 1);
+            }
+            if message.corrected > 0 {
+                println!("corrected {} damaged bytes", message.corrected);
             }
-            None => (),
+            match Cli::open_message(message, output_str.clone(), secret) {
+                Ok(()) => {}
+                Err(e) if total == 1 => return Err(e),
+                Err(e) => {
+                    println!("ERROR: {e}");
+                    failed += 1;
+                }
+            }
+        }
+        match failed {
+            0 => Ok(()),
+            n => Err(format!("{n} of {total} messages could not be decoded").into()),
         }
     }
 
-    fn decode(file_path_str: String, chunk_type_str: String) -> Option<String> {
-        let file_path = Path::new(&file_path_str);
+    fn open_message(
+        message: Message,
+        output_str: Option<String>,
+        secret: &SecretArgs,
+    ) -> Result<()> {
+        let Some(payload) = message.payload else {
+            if message.missing_count == 0 {
+                return Err("message is damaged beyond repair".into());
+            }
+            let missing: Vec<String> = message
+                .missing
+                .iter()
+                .map(|s| format!("{}", s + 1))
+                .collect();
+            let more = message.missing_count as usize - missing.len();
+            let more = match more {
+                0 => String::new(),
+                n => format!(" and {n} more"),
+            };
+            return Err(format!("missing parts {}{more}", missing.join(", ")).into());
+        };
+        Cli::open_payload(payload, output_str, secret)
+    }
 
-        let file_bytes = std::fs::read(&file_path).unwrap();
-        let png = Png::try_from(&file_bytes[..]).unwrap();
+    fn open_payload(
+        mut payload: Vec<u8>,
+        output_str: Option<String>,
+        secret: &SecretArgs,
+    ) -> Result<()> {
+        if crypto::is_encrypted(&payload) {
+            match secret.secret()? {
+                Some(secret) => payload = crypto::decrypt(&payload, &secret)?,
+                None => {
+                    return Err(
+                        "message is encrypted, pass --password, --key-file or --identity".into(),
+                    )
+                }
+            }
+        }
+        if compress::is_compressed(&payload) {
+            payload = compress::decompress(&payload)?;
+        }
 
-        match png.chunk_by_type(&chunk_type_str[..]) {
-            Some(chunk) => Some(chunk.data_as_string().unwrap()),
-            None => None,
+        if attachment::is_attachment(&payload) {
+            let attachment = Attachment::try_from(&payload[..])?;
+            // Only the final component of the stored name is trusted, so a
+            // crafted file cannot write outside the current directory.
+            // The stored name is chosen by whoever made the image, so it
+            // never replaces an existing file; --output is taken as asked.
+            let output_str = match output_str {
+                Some(output_str) => {
+                    std::fs::write(&output_str, attachment.data())?;
+                    output_str
+                }
+                None => {
+                    let name = Path::new(attachment.name())
+                        .file_name()
+                        .and_then(|name| name.to_str())
+                        .ok_or("embedded file has no usable name, pass --output")?
+                        .to_string();
+                    let mut file = match std::fs::OpenOptions::new()
+                        .write(true)
+                        .create_new(true)
+                        .open(&name)
+                    {
+                        Ok(file) => file,
+                        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                            return Err(format!("{name} already exists, pass --output").into())
+                        }
+                        Err(e) => return Err(e.into()),
+                    };
+                    file.write_all(attachment.data())?;
+                    name
+                }
+            };
+            println!(
+                "restored {} ({}), {} bytes to {output_str}",
+                attachment.name(),
+                attachment.mime(),
+                attachment.data().len()
+            );
+            return Ok(());
         }
+
+        match output_str {
+            Some(output_str) => std::fs::write(output_str, payload)?,
+            None => println!("{}", String::from_utf8(payload)?),
+        }
+        Ok(())
     }
 
-    fn remove(file_path_str: String, chunk_type_str: String) {
+    fn remove(file_path_str: String, chunk_type_str: String, selector: &Selector) -> Result<()> {
         let file_path = Path::new(&file_path_str);
+        let mut png = Png::from_file(file_path)?;
+        let moved = png.adopt_trailing_chunks();
+        if moved > 0 {
+            println!("moved {moved} chunks from after IEND to before it");
+        }
+        let chunk_type = ChunkType::from_str(&chunk_type_str[..])?;
 
-        let file_bytes = std::fs::read(&file_path).unwrap();
-        let mut png = Png::try_from(&file_bytes[..]).unwrap();
+        let found = message::find(&png, &chunk_type, selector)?;
+        if found.is_empty() {
+            return Err(format!("no {chunk_type} chunk found").into());
+        }
+        // A split message goes as a whole.
+        let positions: Vec<usize> = found.iter().flat_map(|m| m.chunks.clone()).collect();
+        println!("removed {} chunks", positions.len());
 
-        match png.remove_chunk(&chunk_type_str[..]) {
-            Ok(_) => match std::fs::write(file_path, png.as_bytes()) {
-                Err(e) => eprintln!("ERROR: {e}"),
-                Ok(_) => (),
-            },
-            Err(e) => eprintln!("ERROR: {e}"),
+        let mut index = 0;
+        png.remove_where(|_| {
+            index += 1;
+            positions.contains(&(index - 1))
+        });
+        std::fs::write(file_path, png.as_bytes())?;
+        Ok(())
+    }
+
+    fn list(file_path_str: String) -> Result<()> {
+        let mut png = Png::from_file(&file_path_str)?;
+        png.adopt_trailing_chunks();
+
+        let mut counts: Vec<(ChunkType, usize)> = Vec::new();
+        for message in message::messages(&png) {
+            let index = match counts.iter_mut().find(|(t, _)| *t == message.chunk_type) {
+                Some((_, count)) => {
+                    *count += 1;
+                    *count - 1
+                }
+                None => {
+                    counts.push((message.chunk_type.clone(), 1));
+                    0
+                }
+            };
+
+            let kind = match &message.payload {
+                None if message.missing_count == 0 => "damaged beyond repair".to_string(),
+                None => format!("{} parts missing", message.missing_count),
+                Some(payload) if crypto::is_encrypted(payload) => {
+                    format!("encrypted, {} bytes", payload.len())
+                }
+                Some(payload) if compress::is_compressed(payload) => format!(
+                    "compressed, {} -> {} bytes",
+                    compress::inflated_len(payload).unwrap_or(0),
+                    payload.len()
+                ),
+                Some(payload) => match Attachment::try_from(&payload[..]) {
+                    Ok(attachment) => format!(
+                        "file {} ({}), {} bytes",
+                        attachment.name(),
+                        attachment.mime(),
+                        attachment.data().len()
+                    ),
+                    Err(_) => format!("{} bytes", payload.len()),
+                },
+            };
+            let mut split = if message.chunks.len() > 1 {
+                format!(", {} chunks", message.chunks.len())
+            } else {
+                String::new()
+            };
+            if message.corrected > 0 {
+                split += &format!(", {} bytes corrected", message.corrected);
+            }
+            println!(
+                "{} #{index} {}: {kind}{split}",
+                message.chunk_type,
+                message.name.as_deref().unwrap_or("(unnamed)")
+            );
         }
+        Ok(())
     }
 
-    fn print_chunks(file_path_str: String) {
-        let file_path = Path::new(&file_path_str);
-        let file_bytes = std::fs::read(&file_path).unwrap();
-        let png = Png::try_from(&file_bytes[..]).unwrap();
+    fn print_chunks(file_path_str: String) -> Result<()> {
+        let png = Png::container_from_file(&file_path_str)?;
+        if png.format() != Format::Png {
+            println!("{} stream", png.format());
+        }
 
         for chunk in png.chunks() {
-            let messages = chunk.data_as_string().unwrap();
-            println!("{messages}");
+            if crypto::is_encrypted(chunk.data()) {
+                println!(
+                    "{}: encrypted, {} bytes",
+                    chunk.chunk_type(),
+                    chunk.length()
+                );
+                continue;
+            }
+            if let Some(inflated) = compress::inflated_len(chunk.data()) {
+                println!(
+                    "{}: compressed, {inflated} -> {} bytes",
+                    chunk.chunk_type(),
+                    chunk.length()
+                );
+                continue;
+            }
+            if fec::is_protected(chunk.data()) {
+                println!(
+                    "{}: error corrected, {} bytes",
+                    chunk.chunk_type(),
+                    chunk.length()
+                );
+                continue;
+            }
+            if let (Some(name), payload) = message::split_name(chunk.data()) {
+                println!(
+                    "{}: message {name}, {} bytes",
+                    chunk.chunk_type(),
+                    payload.len()
+                );
+                continue;
+            }
+            if let Ok(part) = Part::try_from(chunk.data()) {
+                println!(
+                    "{}: part {}/{} of {:016x}, {} bytes",
+                    chunk.chunk_type(),
+                    part.sequence + 1,
+                    part.total,
+                    part.id,
+                    part.data.len()
+                );
+                continue;
+            }
+            if let Ok(attachment) = Attachment::try_from(chunk.data()) {
+                println!(
+                    "{}: file {} ({}), {} bytes",
+                    chunk.chunk_type(),
+                    attachment.name(),
+                    attachment.mime(),
+                    attachment.data().len()
+                );
+                continue;
+            }
+            if let Some(description) = registry::describe(&chunk) {
+                println!("{}: {description}", chunk.chunk_type());
+                continue;
+            }
+            match chunk.data_as_string() {
+                Ok(message) => println!("{}: {message}", chunk.chunk_type()),
+                Err(_) => println!("{}: {} bytes", chunk.chunk_type(), chunk.length()),
+            }
+        }
+        Ok(())
+    }
+
+    fn info(file_path_str: String) -> Result<()> {
+        let png = Png::from_file(&file_path_str)?;
+        let chunks = png.chunks();
+        if let Some(ihdr) = chunks.first().and_then(registry::describe) {
+            println!("{ihdr}");
+        }
+
+        let mut types: Vec<(ChunkType, usize)> = Vec::new();
+        for chunk in chunks.iter() {
+            match types.iter_mut().find(|(t, _)| t == chunk.chunk_type()) {
+                Some((_, count)) => *count += 1,
+                None => types.push((chunk.chunk_type().clone(), 1)),
+            }
+        }
+        for (chunk_type, count) in types.iter() {
+            match registry::lookup(chunk_type) {
+                Some(info) => println!(
+                    "{chunk_type} x{count}: {} ({})",
+                    info.description, info.spec
+                ),
+                None => println!(
+                    "{chunk_type} x{count}: unregistered {} {} chunk, {}",
+                    if chunk_type.is_critical() {
+                        "critical"
+                    } else {
+                        "ancillary"
+                    },
+                    if chunk_type.is_public() {
+                        "public"
+                    } else {
+                        "private"
+                    },
+                    if chunk_type.is_safe_to_copy() {
+                        "safe to copy"
+                    } else {
+                        "unsafe to copy"
+                    }
+                ),
+            }
+        }
+
+        if !png.trailing_data().is_empty() {
+            println!("{} bytes after IEND", png.trailing_data().len());
+        }
+        for problem in registry::validate(&chunks) {
+            println!("warning: {problem}");
+        }
+        Ok(())
+    }
+
+    fn optimize(
+        file_path_str: String,
+        output_file_str: Option<String>,
+        strip: Vec<String>,
+        no_reduce: bool,
+        keep_unsafe: bool,
+    ) -> Result<()> {
+        let png = Png::from_file(&file_path_str)?;
+
+        let mut options = OptimizeOptions {
+            reduce: !no_reduce,
+            keep_unsafe,
+            ..OptimizeOptions::default()
+        };
+        for chunk_type_str in strip.iter() {
+            options.strip.push(ChunkType::from_str(chunk_type_str)?);
+        }
+
+        if options.reduce && optimize::is_animated(&png) {
+            println!("note: the colour type of animated PNGs is kept");
+        }
+        let optimized = optimize(&png, &options)?;
+
+        for chunk in optimized.stripped.iter() {
+            println!("stripped {} ({} bytes)", chunk.chunk_type(), chunk.length());
+        }
+        for dropped in optimized.dropped.iter() {
+            println!("dropped {}: {}", dropped.chunk.chunk_type(), dropped.reason);
+        }
+        let original = png.ihdr()?;
+        if let Some((ihdr, _, _)) = &optimized.settings {
+            if (ihdr.color_type(), ihdr.bit_depth())
+                != (original.color_type(), original.bit_depth())
+            {
+                println!(
+                    "warning: the colour type changed, so a message hidden in the pixels with a key may be lost; use --no-reduce to keep it"
+                );
+            }
+        }
+        match &optimized.settings {
+            Some((ihdr, filter, level)) => println!(
+                "re-encoded as {} {}-bit, filter {:?}, level {level}",
+                ihdr.color_type(),
+                ihdr.bit_depth(),
+                filter
+            ),
+            None => println!("kept the original image data"),
+        }
+        println!(
+            "{} -> {} bytes",
+            optimized.original_size,
+            optimized.optimized_size()
+        );
+
+        let output_file_path = output_file_str.unwrap_or(file_path_str);
+        std::fs::write(Path::new(&output_file_path), optimized.png.as_bytes())?;
+        Ok(())
+    }
+
+    fn rechunk(
+        file_path_str: String,
+        output_file_str: Option<String>,
+        max_len: Option<usize>,
+    ) -> Result<()> {
+        let mut png = Png::from_file(&file_path_str)?;
+        let count_idats = |png: &Png| {
+            png.chunks()
+                .iter()
+                .filter(|c| c.chunk_type().to_string() == "IDAT")
+                .count()
+        };
+
+        let before = count_idats(&png);
+        png.rechunk_idat(max_len.unwrap_or(i32::MAX as usize))?;
+        println!("{before} -> {} IDAT chunks", count_idats(&png));
+
+        let output_file_path = output_file_str.unwrap_or(file_path_str);
+        std::fs::write(Path::new(&output_file_path), png.as_bytes())?;
+        Ok(())
+    }
+
+    fn keygen(identity_file_str: String, signing: bool) -> Result<()> {
+        let identity_path = Path::new(&identity_file_str);
+        if identity_path.exists() {
+            return Err(format!("{identity_file_str} already exists").into());
         }
+
+        let (secret_hex, public_hex) = if signing {
+            let key = signature::generate_signing_key();
+            (
+                hex::encode(key.to_bytes()),
+                hex::encode(key.verifying_key().as_bytes()),
+            )
+        } else {
+            let (identity, public) = crypto::generate_identity();
+            (
+                hex::encode(identity.to_bytes()),
+                hex::encode(public.as_bytes()),
+            )
+        };
+
+        let mut file = std::fs::OpenOptions::new();
+        file.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut file, 0o600);
+        file.open(identity_path)?
+            .write_all(format!("{secret_hex}\n").as_bytes())?;
+        std::fs::write(
+            format!("{identity_file_str}.pub"),
+            format!("{public_hex}\n"),
+        )?;
+
+        println!("public key: {public_hex}");
+        Ok(())
+    }
+
+    fn sign(
+        file_path_str: String,
+        output_file_str: Option<String>,
+        key_file_str: String,
+        include: Vec<String>,
+        detached: Option<String>,
+    ) -> Result<()> {
+        let png = Png::from_file(&file_path_str)?;
+        let key = signature::signing_key_from_hex(&std::fs::read_to_string(key_file_str)?)?;
+        let included = include
+            .iter()
+            .map(|chunk_type_str| ChunkType::from_str(chunk_type_str))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        // Re-signing replaces any earlier embedded signature.
+        let mut chunks: Vec<Chunk> = png
+            .chunks()
+            .into_iter()
+            .filter(|c| c.chunk_type().to_string() != signature::SIGNATURE_CHUNK)
+            .collect();
+        let signature = signature::sign(&Png::from_chunks(chunks.clone()), &key, &included)?;
+        println!("signed {} chunks", signature.covered().len());
+
+        if let Some(detached_path) = detached {
+            std::fs::write(detached_path, signature.as_bytes())?;
+            return Ok(());
+        }
+
+        let iend = chunks
+            .iter()
+            .position(|c| c.chunk_type().to_string() == "IEND")
+            .unwrap_or(chunks.len());
+        chunks.insert(iend, signature.to_chunk());
+        let mut signed = Png::from_chunks(chunks);
+        signed.set_trailing_data(png.trailing_data().to_vec());
+
+        let output_file_path = output_file_str.unwrap_or(file_path_str);
+        std::fs::write(Path::new(&output_file_path), signed.as_bytes())?;
+        Ok(())
+    }
+
+    fn verify(
+        file_path_str: String,
+        key_str: String,
+        signature_file_str: Option<String>,
+    ) -> Result<()> {
+        let png = Png::from_file(&file_path_str)?;
+        let signature = match signature_file_str {
+            Some(path) => PngSignature::try_from(&std::fs::read(path)?[..])?,
+            None => signature::embedded_signature(&png)?.ok_or("the image is not signed")?,
+        };
+
+        let verification = signature::verify(&png, &signature);
+        for (chunk_type, status) in verification.chunks.iter() {
+            let status = match status {
+                CoverageStatus::Unchanged => "unchanged",
+                CoverageStatus::Changed => "CHANGED",
+                CoverageStatus::Missing => "MISSING",
+                CoverageStatus::Added => "ADDED",
+            };
+            println!("{chunk_type}: {status}");
+        }
+
+        if !png.trailing_data().is_empty() {
+            println!(
+                "note: {} bytes after IEND are not covered",
+                png.trailing_data().len()
+            );
+        }
+
+        let signer = hex::encode(verification.public_key.as_bytes());
+        println!("signed by {signer}");
+        // The key comes from the signature itself, so anyone who edits the
+        // image can sign it again; only a trusted key proves who signed it.
+        let trusted = match signature::verifying_key_from_hex(&key_str) {
+            Ok(key) => key,
+            Err(_) => signature::verifying_key_from_hex(&std::fs::read_to_string(key_str)?)?,
+        };
+        if trusted != verification.public_key {
+            return Err("signed by an untrusted key".into());
+        }
+
+        if !verification.signature_valid {
+            return Err("the signature is invalid".into());
+        }
+        if !verification.is_intact() {
+            return Err("the image changed since it was signed".into());
+        }
+        println!("signature OK");
+        Ok(())
+    }
+
+    fn trailing(
+        file_path_str: String,
+        output_file_str: Option<String>,
+        extract: Option<String>,
+        strip: bool,
+    ) -> Result<()> {
+        let mut png = Png::from_file(&file_path_str)?;
+        let trailing = png.trailing_data();
+        if trailing.is_empty() {
+            println!("no data after IEND");
+            return Ok(());
+        }
+
+        let kind = if trailing.starts_with(b"PK\x03\x04") {
+            " (ZIP archive)"
+        } else {
+            ""
+        };
+        println!("{} bytes after IEND{kind}", trailing.len());
+
+        if let Some(extract_path) = extract {
+            std::fs::write(extract_path, trailing)?;
+        }
+        if strip {
+            png.strip_trailing_data();
+            let output_file_path = output_file_str.unwrap_or(file_path_str);
+            std::fs::write(Path::new(&output_file_path), png.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn repair(file_path_str: String, output_file_str: String, keep_bad_crc: bool) -> Result<()> {
+        let bytes = std::fs::read(&file_path_str)?;
+        let recovery = recover::recover(&bytes, keep_bad_crc);
+        for damage in recovery.damage.iter() {
+            println!("offset {}: {}", damage.offset, damage.message);
+        }
+        if recovery.damage.is_empty() {
+            println!("no damage found");
+        }
+
+        let repaired = recovery.repaired()?;
+        println!("recovered {} chunks", recovery.png.chunks().len());
+        std::fs::write(Path::new(&output_file_str), repaired.as_bytes())?;
+        Ok(())
+    }
+
+    fn strip(files: &[String], rule: &Rule, dry_run: bool) -> Result<()> {
+        let mut total = 0;
+        let mut failed = 0;
+        for file in files.iter() {
+            let result = Png::from_file(file).and_then(|mut png| {
+                let removed = strip::strip(&mut png, rule)?;
+                if !dry_run && !removed.is_empty() {
+                    std::fs::write(Path::new(file), png.as_bytes())?;
+                }
+                Ok((png, removed))
+            });
+            let (png, removed) = match result {
+                Ok(stripped) => stripped,
+                Err(e) => {
+                    println!("{file}: ERROR: {e}");
+                    failed += 1;
+                    continue;
+                }
+            };
+
+            if removed.is_empty() {
+                println!("{file}: nothing to strip");
+            } else {
+                let saved: usize = removed.iter().map(|c| c.as_bytes().len()).sum();
+                let types: Vec<String> =
+                    removed.iter().map(|c| c.chunk_type().to_string()).collect();
+                println!("{file}: removed {}, {saved} bytes saved", types.join(", "));
+                total += saved;
+            }
+            if !png.trailing_data().is_empty() {
+                println!(
+                    "  warning: {} bytes after IEND are kept, see `trailing --strip`",
+                    png.trailing_data().len()
+                );
+            }
+        }
+
+        if files.len() > 1 {
+            println!("{total} bytes saved in total");
+        }
+        if dry_run {
+            println!("dry run, no files were changed");
+        }
+        match failed {
+            0 => Ok(()),
+            n => Err(format!("{n} of {} files could not be stripped", files.len()).into()),
+        }
+    }
+
+    fn fix_crc(
+        file_path_str: String,
+        output_file_str: Option<String>,
+        dry_run: bool,
+    ) -> Result<()> {
+        let bytes = std::fs::read(&file_path_str)?;
+        let (fixed, mismatches) = recover::fix_crcs(&bytes)?;
+        for mismatch in mismatches.iter() {
+            println!(
+                "{} at offset {}: stored {:08x}, computed {:08x}",
+                mismatch.chunk_type, mismatch.offset, mismatch.stored, mismatch.computed
+            );
+        }
+
+        match (mismatches.len(), dry_run) {
+            (0, _) => println!("all CRCs are correct"),
+            (n, true) => println!("would fix {n} chunks"),
+            (n, false) => {
+                let output_file_path = output_file_str.unwrap_or(file_path_str);
+                std::fs::write(Path::new(&output_file_path), fixed)?;
+                println!("fixed {n} chunks");
+            }
+        }
+        Ok(())
+    }
+
+    fn extract_images(file_path_str: String, output_dir_str: String) -> Result<()> {
+        let container = Png::container_from_file(&file_path_str)?;
+        let output_dir = Path::new(&output_dir_str);
+        std::fs::create_dir_all(output_dir)?;
+
+        for (index, image) in mng::embedded_images(&container)?.iter().enumerate() {
+            let path = output_dir.join(format!("image_{index:03}.{}", image.extension()));
+            std::fs::write(&path, image.as_bytes())?;
+            println!("wrote {}", path.display());
+        }
+        Ok(())
+    }
+
+    fn suggest_chunk_type(seed: Option<String>) -> Result<()> {
+        let seed = seed.unwrap_or_else(|| rand::random::<u64>().to_string());
+        println!("{}", ChunkType::private_ancillary(&seed));
+        Ok(())
+    }
+
+    fn check_chunk_type(chunk_type_str: &str) -> Result<()> {
+        let chunk_type = ChunkType::from_str(chunk_type_str)?;
+        let problems = chunk_type_problems(&chunk_type);
+        if problems.is_empty() {
+            println!("{chunk_type} is suited to custom payloads");
+            return Ok(());
+        }
+        for problem in problems.iter() {
+            println!("{chunk_type} {problem}");
+        }
+        println!("consider {}", ChunkType::private_ancillary(chunk_type_str));
+        Ok(())
+    }
+
+    fn apng_info(file_path_str: String) -> Result<()> {
+        let png = Png::from_file(&file_path_str)?;
+        let animation = Animation::try_from(&png)?;
+
+        let plays = match animation.control.num_plays {
+            0 => "forever".to_string(),
+            n => format!("{n} times"),
+        };
+        println!(
+            "{} frames, {} ms per play, played {plays}",
+            animation.frames.len(),
+            animation.duration().as_millis()
+        );
+        if !animation.has_default_frame() {
+            println!("the IDAT image is not part of the animation");
+        }
+        for (i, frame) in animation.frames.iter().enumerate() {
+            let control = &frame.control;
+            println!(
+                "frame {i}: {}x{} at {},{}, {} ms, dispose {}, blend {}",
+                control.width,
+                control.height,
+                control.x_offset,
+                control.y_offset,
+                control.delay().as_millis(),
+                control.dispose_op,
+                control.blend_op
+            );
+        }
+        for problem in apng::validate(&png)?.iter() {
+            println!("warning: {problem}");
+        }
+        Ok(())
+    }
+
+    fn apng_extract(
+        file_path_str: String,
+        output_dir_str: String,
+        frame: Option<usize>,
+    ) -> Result<()> {
+        let png = Png::from_file(&file_path_str)?;
+        let animation = Animation::try_from(&png)?;
+        let indexes = match frame {
+            Some(index) => vec![index],
+            None => (0..animation.frames.len()).collect(),
+        };
+
+        let output_dir = Path::new(&output_dir_str);
+        std::fs::create_dir_all(output_dir)?;
+        for index in indexes {
+            let frame_png = animation.frame_png(&png, index)?;
+            let path = output_dir.join(format!("frame_{index:03}.png"));
+            std::fs::write(&path, frame_png.as_bytes())?;
+            println!("wrote {}", path.display());
+        }
+        Ok(())
+    }
+
+    fn apng_build(
+        frame_paths: &[String],
+        output_file_str: String,
+        delay: (u16, u16),
+        loops: u32,
+    ) -> Result<()> {
+        let frames = frame_paths
+            .iter()
+            .map(Png::from_file)
+            .collect::<Result<Vec<Png>>>()?;
+        let png = apng::build(&frames, delay, loops)?;
+        std::fs::write(Path::new(&output_file_str), png.as_bytes())?;
+        println!("wrote {} frames to {output_file_str}", frames.len());
+        Ok(())
+    }
+
+    fn detect(files: &[String]) -> Result<()> {
+        let mut failed = 0;
+        for file in files.iter() {
+            let report = match std::fs::read(file)
+                .map_err(|e| e.into())
+                .and_then(|bytes| detect::analyze(&bytes))
+            {
+                Ok(report) => report,
+                Err(e) => {
+                    println!("{file}: ERROR: {e}");
+                    failed += 1;
+                    continue;
+                }
+            };
+
+            let verdict = if report.is_suspicious() {
+                "SUSPICIOUS"
+            } else {
+                "clean"
+            };
+            println!("{file}: {verdict}");
+            for finding in report.findings.iter() {
+                println!("  {}: {}", finding.level, finding.message);
+            }
+            if let Some((p, share)) = report.chi_square {
+                println!(
+                    "  chi-square: p = {p:.3} over the first {:.0}% of samples",
+                    share * 100.0
+                );
+            }
+            if let Some(rate) = report.rs_rate {
+                println!("  RS estimate: {:.1}% of LSBs", rate * 100.0);
+            }
+        }
+        match failed {
+            0 => Ok(()),
+            n => Err(format!("{n} of {} files could not be scanned", files.len()).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::Encoder;
+    use crate::ihdr::{ColorType, Ihdr};
+
+    fn run(args: &[&str]) -> Result<()> {
+        Cli::try_parse_from([&["pngme"], args].concat())?.run()
+    }
+
+    fn testing_png() -> Png {
+        Encoder::new(Ihdr::new(2, 2, ColorType::Grayscale, 8).unwrap())
+            .encode(&[1, 2, 3, 4])
+            .unwrap()
+    }
+
+    fn testing_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("pngme-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_decode_message_under_public_type() {
+        let dir = testing_dir("public");
+        let file = dir.join("f.png");
+        let output = dir.join("out.txt");
+        std::fs::write(&file, testing_png().as_bytes()).unwrap();
+        let (file, output) = (file.to_str().unwrap(), output.to_str().unwrap());
+
+        run(&["encode", file, "RuSt", "hi"]).unwrap();
+        run(&["decode", file, "RuSt", "--output", output]).unwrap();
+        let decoded = std::fs::read(output).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(decoded, b"hi");
+    }
+
+    #[test]
+    fn test_decode_all_goes_past_incomplete_messages() {
+        let dir = testing_dir("incomplete");
+        let file = dir.join("f.png");
+        let mut png = testing_png();
+        let rust = ChunkType::from_str("ruSt").unwrap();
+        let named = message::with_name("big", &[5; 30]).unwrap();
+        for part in split::split(&named, 10).unwrap().iter().skip(1) {
+            png.append_chunk(Chunk::new(rust.clone(), part.as_bytes()));
+        }
+        png.append_chunk(Chunk::new(rust, b"complete".to_vec()));
+        std::fs::write(&file, png.as_bytes()).unwrap();
+
+        let result = run(&["decode", file.to_str().unwrap(), "ruSt", "--all"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "1 of 2 messages could not be decoded"
+        );
     }
 }
@@ -0,0 +1,89 @@
+use std::io::{Read, Write};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+/// Marks a payload deflated before it was encrypted and embedded.
+pub const MAGIC: [u8; 4] = *b"pmZ\x01";
+
+/// Magic plus the inflated length.
+const HEADER_LEN: usize = 4 + 4;
+
+/// The largest payload `compress` writes and `decompress` inflates. The
+/// stored length is chosen by whoever made the file, so it cannot be the
+/// only bound.
+pub const MAX_LEN: usize = 256 << 20;
+
+pub fn is_compressed(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+/// Deflates `payload` at the best compression level.
+pub fn compress(payload: &[u8]) -> crate::Result<Vec<u8>> {
+    if payload.len() > MAX_LEN {
+        return Err("payload is too large to compress".into());
+    }
+    let len = payload.len() as u32;
+    let mut out = MAGIC.to_vec();
+    out.extend(len.to_be_bytes());
+
+    let mut encoder = ZlibEncoder::new(out, Compression::best());
+    encoder.write_all(payload)?;
+    Ok(encoder.finish()?)
+}
+
+/// The payload inflated by `compress`. The output is capped by the stored
+/// length, which may not exceed `MAX_LEN`.
+pub fn decompress(data: &[u8]) -> crate::Result<Vec<u8>> {
+    if !is_compressed(data) || data.len() < HEADER_LEN {
+        return Err("payload is not compressed".into());
+    }
+    let len = u32::from_be_bytes(data[4..HEADER_LEN].try_into().unwrap()) as usize;
+    if len > MAX_LEN {
+        return Err(format!("compressed payload claims {len} bytes, more than allowed").into());
+    }
+
+    let mut out = Vec::new();
+    ZlibDecoder::new(&data[HEADER_LEN..])
+        .take(len as u64 + 1)
+        .read_to_end(&mut out)?;
+    if out.len() != len {
+        return Err(format!(
+            "compressed payload should inflate to {len} bytes, got {}",
+            out.len()
+        )
+        .into());
+    }
+    Ok(out)
+}
+
+/// The inflated length recorded by `compress`.
+pub fn inflated_len(data: &[u8]) -> Option<usize> {
+    if !is_compressed(data) || data.len() < HEADER_LEN {
+        return None;
+    }
+    Some(u32::from_be_bytes(data[4..HEADER_LEN].try_into().unwrap()) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compress_round_trip() {
+        let payload = b"repetitive text, repetitive text, repetitive text".repeat(20);
+        let compressed = compress(&payload).unwrap();
+        assert!(is_compressed(&compressed));
+        assert!(compressed.len() < payload.len());
+        assert_eq!(inflated_len(&compressed), Some(payload.len()));
+        assert_eq!(decompress(&compressed).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_decompress_checks_length() {
+        let mut compressed = compress(&[0; 1000]).unwrap();
+        compressed[7] = 10;
+        assert!(decompress(&compressed).is_err());
+        compressed[4..8].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(decompress(&compressed).is_err());
+    }
+}
@@ -0,0 +1,400 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+/// Marks a payload as an envelope encrypted with a password or key file.
+pub const MAGIC: [u8; 4] = *b"pmE\x01";
+/// Marks a payload as an envelope encrypted to X25519 recipients.
+pub const RECIPIENTS_MAGIC: [u8; 4] = *b"pmR\x01";
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+/// Magic, KDF id, three KDF parameters, salt and nonce.
+const HEADER_LEN: usize = 4 + 1 + 12 + SALT_LEN + NONCE_LEN;
+
+/// Upper bounds on the stored KDF parameters, so a crafted file cannot make
+/// decoding allocate gigabytes or spin for minutes.
+const MAX_KDF_PARAMS: KdfParams = KdfParams {
+    m_cost: 1 << 20,
+    t_cost: 16,
+    p_cost: 16,
+};
+
+const KDF_ARGON2ID: u8 = 0;
+const KDF_KEY_FILE: u8 = 1;
+
+/// What the encryption key is derived from.
+pub enum Secret {
+    /// A passphrase, stretched with Argon2id.
+    Password(String),
+    /// High-entropy key material, such as the contents of a key file.
+    Key(Vec<u8>),
+    /// An X25519 secret key, for messages encrypted to recipients.
+    Identity(StaticSecret),
+}
+
+/// Argon2id cost parameters, stored in every envelope so they can be raised
+/// without breaking older files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    /// Memory in KiB.
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams {
+            m_cost: 19 * 1024,
+            t_cost: 2,
+            p_cost: 1,
+        }
+    }
+}
+
+fn derive_key(secret: &Secret, params: &KdfParams, salt: &[u8]) -> crate::Result<[u8; 32]> {
+    let mut key = [0; 32];
+    match secret {
+        Secret::Password(password) => {
+            let params = Params::new(params.m_cost, params.t_cost, params.p_cost, Some(32))
+                .map_err(|e| format!("invalid KDF parameters: {e}"))?;
+            Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                .hash_password_into(password.as_bytes(), salt, &mut key)
+                .map_err(|e| format!("key derivation failed: {e}"))?;
+        }
+        Secret::Key(material) => {
+            let mut hasher = Sha256::new();
+            hasher.update(salt);
+            hasher.update(material);
+            key.copy_from_slice(&hasher.finalize());
+        }
+        Secret::Identity(_) => return Err("an identity only opens recipient messages".into()),
+    }
+    Ok(key)
+}
+
+pub fn is_encrypted(data: &[u8]) -> bool {
+    (data.len() >= HEADER_LEN && data[..4] == MAGIC)
+        || (data.len() >= RECIPIENTS_HEADER_LEN && data[..4] == RECIPIENTS_MAGIC)
+}
+
+/// Encrypts `payload` with XChaCha20-Poly1305 under a key derived from
+/// `secret`. The header (KDF parameters, salt and nonce) is authenticated too.
+pub fn encrypt(payload: &[u8], secret: &Secret, params: &KdfParams) -> crate::Result<Vec<u8>> {
+    let mut salt = [0; SALT_LEN];
+    let mut nonce = [0; NONCE_LEN];
+    OsRng.fill_bytes(&mut salt);
+    OsRng.fill_bytes(&mut nonce);
+
+    let kdf = match secret {
+        Secret::Password(_) => KDF_ARGON2ID,
+        Secret::Key(_) => KDF_KEY_FILE,
+        Secret::Identity(_) => return Err("use encrypt_to_recipients with public keys".into()),
+    };
+    let mut envelope: Vec<u8> = MAGIC
+        .iter()
+        .chain([kdf].iter())
+        .chain(params.m_cost.to_be_bytes().iter())
+        .chain(params.t_cost.to_be_bytes().iter())
+        .chain(params.p_cost.to_be_bytes().iter())
+        .chain(salt.iter())
+        .chain(nonce.iter())
+        .copied()
+        .collect();
+
+    let key = derive_key(secret, params, &salt)?;
+    let cipher = XChaCha20Poly1305::new(&key.into());
+    let ciphertext = cipher
+        .encrypt(
+            XNonce::from_slice(&nonce),
+            Payload {
+                msg: payload,
+                aad: &envelope,
+            },
+        )
+        .map_err(|_| "encryption failed")?;
+
+    envelope.extend(ciphertext);
+    Ok(envelope)
+}
+
+/// Reverses `encrypt`, failing if the key is wrong or the data was altered.
+pub fn decrypt(envelope: &[u8], secret: &Secret) -> crate::Result<Vec<u8>> {
+    if !is_encrypted(envelope) {
+        return Err("not an encrypted message".into());
+    }
+    if envelope[..4] == RECIPIENTS_MAGIC {
+        return match secret {
+            Secret::Identity(identity) => decrypt_with_identity(envelope, identity),
+            _ => Err("message was encrypted to recipients, pass an identity".into()),
+        };
+    }
+    if let Secret::Identity(_) = secret {
+        return Err("message was not encrypted to recipients".into());
+    }
+
+    let header = &envelope[..HEADER_LEN];
+    let read_u32 = |at: usize| u32::from_be_bytes(header[at..at + 4].try_into().unwrap());
+    let params = KdfParams {
+        m_cost: read_u32(5),
+        t_cost: read_u32(9),
+        p_cost: read_u32(13),
+    };
+    if params.m_cost > MAX_KDF_PARAMS.m_cost
+        || params.t_cost > MAX_KDF_PARAMS.t_cost
+        || params.p_cost > MAX_KDF_PARAMS.p_cost
+    {
+        return Err("KDF parameters exceed the supported limits".into());
+    }
+    let salt = &header[17..17 + SALT_LEN];
+    let nonce = &header[17 + SALT_LEN..];
+
+    match (header[4], secret) {
+        (KDF_ARGON2ID, Secret::Password(_)) | (KDF_KEY_FILE, Secret::Key(_)) => (),
+        (KDF_ARGON2ID, _) => return Err("message was encrypted with a password".into()),
+        (KDF_KEY_FILE, _) => return Err("message was encrypted with a key file".into()),
+        _ => return Err("unknown key derivation function".into()),
+    }
+
+    let key = derive_key(secret, &params, salt)?;
+    let cipher = XChaCha20Poly1305::new(&key.into());
+    cipher
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: &envelope[HEADER_LEN..],
+                aad: header,
+            },
+        )
+        .map_err(|_| "wrong key or corrupted message".into())
+}
+
+const WRAPPED_KEY_LEN: usize = 32 + 16;
+/// Magic, ephemeral public key, recipient count, one wrapped key and nonce.
+const RECIPIENTS_HEADER_LEN: usize = 4 + 32 + 1 + WRAPPED_KEY_LEN + NONCE_LEN;
+
+/// Creates a new X25519 identity for `keygen`.
+pub fn generate_identity() -> (StaticSecret, PublicKey) {
+    let identity = StaticSecret::random_from_rng(OsRng);
+    let public = PublicKey::from(&identity);
+    (identity, public)
+}
+
+pub fn public_key_from_hex(hex_key: &str) -> crate::Result<PublicKey> {
+    let bytes: [u8; 32] = hex::decode(hex_key.trim())?
+        .try_into()
+        .map_err(|_| "an X25519 key is 32 bytes")?;
+    Ok(PublicKey::from(bytes))
+}
+
+pub fn identity_from_hex(hex_key: &str) -> crate::Result<StaticSecret> {
+    let bytes: [u8; 32] = hex::decode(hex_key.trim())?
+        .try_into()
+        .map_err(|_| "an X25519 key is 32 bytes")?;
+    Ok(StaticSecret::from(bytes))
+}
+
+fn wrapping_key(ephemeral: &PublicKey, recipient: &PublicKey, shared: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(b"pngme x25519 recipient\0");
+    hasher.update(ephemeral.as_bytes());
+    hasher.update(recipient.as_bytes());
+    hasher.update(shared);
+    hasher.finalize().into()
+}
+
+/// Encrypts `payload` so that any one of `recipients` can read it. A random
+/// file key encrypts the payload and is wrapped once per recipient with a key
+/// agreed between an ephemeral X25519 key and the recipient's public key.
+/// Recipients are not listed in the envelope; an identity tries every slot.
+pub fn encrypt_to_recipients(payload: &[u8], recipients: &[PublicKey]) -> crate::Result<Vec<u8>> {
+    if recipients.is_empty() || recipients.len() > u8::MAX as usize {
+        return Err("between 1 and 255 recipients are supported".into());
+    }
+
+    let mut file_key = [0; 32];
+    let mut nonce = [0; NONCE_LEN];
+    OsRng.fill_bytes(&mut file_key);
+    OsRng.fill_bytes(&mut nonce);
+
+    let ephemeral = StaticSecret::random_from_rng(OsRng);
+    let ephemeral_public = PublicKey::from(&ephemeral);
+
+    let mut envelope: Vec<u8> = RECIPIENTS_MAGIC
+        .iter()
+        .chain(ephemeral_public.as_bytes().iter())
+        .chain([recipients.len() as u8].iter())
+        .copied()
+        .collect();
+
+    for recipient in recipients.iter() {
+        let shared = ephemeral.diffie_hellman(recipient);
+        let key = wrapping_key(&ephemeral_public, recipient, shared.as_bytes());
+        let wrapped = XChaCha20Poly1305::new(&key.into())
+            .encrypt(XNonce::from_slice(&[0; NONCE_LEN]), &file_key[..])
+            .map_err(|_| "encryption failed")?;
+        envelope.extend(wrapped);
+    }
+    envelope.extend(nonce);
+
+    let ciphertext = XChaCha20Poly1305::new(&file_key.into())
+        .encrypt(
+            XNonce::from_slice(&nonce),
+            Payload {
+                msg: payload,
+                aad: &envelope,
+            },
+        )
+        .map_err(|_| "encryption failed")?;
+
+    envelope.extend(ciphertext);
+    Ok(envelope)
+}
+
+fn decrypt_with_identity(envelope: &[u8], identity: &StaticSecret) -> crate::Result<Vec<u8>> {
+    let ephemeral_public: [u8; 32] = envelope[4..36].try_into().unwrap();
+    let ephemeral_public = PublicKey::from(ephemeral_public);
+    let count = envelope[36] as usize;
+
+    let header_len = 37 + count * WRAPPED_KEY_LEN + NONCE_LEN;
+    if count == 0 || envelope.len() < header_len {
+        return Err("truncated recipient header".into());
+    }
+    let header = &envelope[..header_len];
+    let nonce = &header[header_len - NONCE_LEN..];
+
+    let public = PublicKey::from(identity);
+    let shared = identity.diffie_hellman(&ephemeral_public);
+    let key = wrapping_key(&ephemeral_public, &public, shared.as_bytes());
+    let unwrapper = XChaCha20Poly1305::new(&key.into());
+
+    let file_key = header[37..37 + count * WRAPPED_KEY_LEN]
+        .chunks(WRAPPED_KEY_LEN)
+        .find_map(|wrapped| {
+            unwrapper
+                .decrypt(XNonce::from_slice(&[0; NONCE_LEN]), wrapped)
+                .ok()
+        })
+        .ok_or("this identity is not a recipient of the message")?;
+    let file_key: [u8; 32] = file_key
+        .try_into()
+        .map_err(|_| "malformed recipient header")?;
+
+    XChaCha20Poly1305::new(&file_key.into())
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: &envelope[header_len..],
+                aad: header,
+            },
+        )
+        .map_err(|_| "wrong key or corrupted message".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAST: KdfParams = KdfParams {
+        m_cost: 64,
+        t_cost: 1,
+        p_cost: 1,
+    };
+
+    #[test]
+    fn test_password_round_trip() {
+        let secret = Secret::Password("hunter2".to_string());
+        let envelope = encrypt(b"attack at dawn", &secret, &FAST).unwrap();
+
+        assert!(is_encrypted(&envelope));
+        assert!(!envelope.windows(6).any(|w| w == b"attack"));
+        assert_eq!(decrypt(&envelope, &secret).unwrap(), b"attack at dawn");
+    }
+
+    #[test]
+    fn test_wrong_password_fails() {
+        let envelope = encrypt(b"secret", &Secret::Password("a".to_string()), &FAST).unwrap();
+        assert!(decrypt(&envelope, &Secret::Password("b".to_string())).is_err());
+        assert!(decrypt(&envelope, &Secret::Key(b"a".to_vec())).is_err());
+    }
+
+    #[test]
+    fn test_key_file_round_trip() {
+        let secret = Secret::Key(vec![7; 32]);
+        let envelope = encrypt(b"payload", &secret, &FAST).unwrap();
+        assert_eq!(decrypt(&envelope, &secret).unwrap(), b"payload");
+    }
+
+    #[test]
+    fn test_tampering_is_detected() {
+        let secret = Secret::Password("pw".to_string());
+        let mut envelope = encrypt(b"payload", &secret, &FAST).unwrap();
+
+        let last = envelope.len() - 1;
+        envelope[last] ^= 1;
+        assert!(decrypt(&envelope, &secret).is_err());
+
+        let mut envelope = encrypt(b"payload", &secret, &FAST).unwrap();
+        envelope[20] ^= 1;
+        assert!(decrypt(&envelope, &secret).is_err());
+    }
+
+    #[test]
+    fn test_oversized_kdf_parameters_are_rejected() {
+        let secret = Secret::Password("pw".to_string());
+        let mut envelope = encrypt(b"payload", &secret, &FAST).unwrap();
+        envelope[10] = 0xff;
+        assert!(decrypt(&envelope, &secret).is_err());
+    }
+
+    #[test]
+    fn test_recipients_round_trip() {
+        let (alice, alice_public) = generate_identity();
+        let (bob, bob_public) = generate_identity();
+        let (eve, _) = generate_identity();
+
+        let envelope = encrypt_to_recipients(b"team only", &[alice_public, bob_public]).unwrap();
+        assert!(is_encrypted(&envelope));
+
+        let alice = Secret::Identity(alice);
+        let bob = Secret::Identity(bob);
+        assert_eq!(decrypt(&envelope, &alice).unwrap(), b"team only");
+        assert_eq!(decrypt(&envelope, &bob).unwrap(), b"team only");
+        assert!(decrypt(&envelope, &Secret::Identity(eve)).is_err());
+        assert!(decrypt(&envelope, &Secret::Password("pw".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_keys_round_trip_through_hex() {
+        let (identity, public) = generate_identity();
+        let parsed = identity_from_hex(&hex::encode(identity.to_bytes())).unwrap();
+        assert_eq!(PublicKey::from(&parsed), public);
+        assert_eq!(
+            public_key_from_hex(&format!("{}\n", hex::encode(public.as_bytes()))).unwrap(),
+            public
+        );
+        assert!(public_key_from_hex("abcd").is_err());
+    }
+
+    #[test]
+    fn test_recipient_header_is_authenticated() {
+        let (alice, alice_public) = generate_identity();
+        let mut envelope = encrypt_to_recipients(b"payload", &[alice_public]).unwrap();
+        let nonce_at = 37 + WRAPPED_KEY_LEN;
+        envelope[nonce_at] ^= 1;
+        assert!(decrypt(&envelope, &Secret::Identity(alice)).is_err());
+    }
+
+    #[test]
+    fn test_plain_data_is_not_encrypted() {
+        assert!(!is_encrypted(
+            b"just a message that is long enough to hold a header"
+        ));
+    }
+}
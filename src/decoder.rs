@@ -0,0 +1,417 @@
+use std::io::Read;
+
+use flate2::read::ZlibDecoder;
+
+use crate::{
+    encoder::Encoder,
+    filter::unfilter_image,
+    ihdr::{ColorType, Ihdr},
+    png::Png,
+};
+
+/// Starting column, starting row, column step and row step of each Adam7 pass.
+const ADAM7_PASSES: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// Decoded pixel data, stored as unfiltered, non-interlaced scanlines in the
+/// colour type and bit depth described by the IHDR.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    ihdr: Ihdr,
+    palette: Option<Vec<u8>>,
+    transparency: Option<Vec<u8>>,
+    pixels: Vec<u8>,
+}
+
+impl TryFrom<&Png> for Image {
+    type Error = crate::Error;
+
+    fn try_from(png: &Png) -> Result<Self, Self::Error> {
+        let ihdr = png.ihdr()?;
+
+        // Inflate no more than the image can hold, so a small IDAT cannot
+        // expand without bound.
+        let expected = filtered_len(&ihdr);
+        let mut inflated = Vec::new();
+        ZlibDecoder::new(&png.idat_data()[..])
+            .take(expected as u64 + 1)
+            .read_to_end(&mut inflated)?;
+        if inflated.len() > expected {
+            return Err("image data inflates past the size of the image".into());
+        }
+        if inflated.len() < expected {
+            return Err("not enough image data".into());
+        }
+
+        let pixels = if ihdr.is_interlaced() {
+            deinterlace(&ihdr, &inflated)?
+        } else {
+            unfilter_image(
+                &inflated,
+                ihdr.row_bytes(),
+                ihdr.height() as usize,
+                ihdr.filter_bpp(),
+            )?
+        };
+
+        let palette = png.chunk_by_type("PLTE").map(|c| c.data().to_vec());
+        if ihdr.color_type() == ColorType::Indexed && palette.is_none() {
+            return Err("indexed image without a PLTE chunk".into());
+        }
+
+        Ok(Image {
+            ihdr: non_interlaced(&ihdr),
+            palette,
+            transparency: png.chunk_by_type("tRNS").map(|c| c.data().to_vec()),
+            pixels,
+        })
+    }
+}
+
+impl Image {
+    pub fn new(ihdr: Ihdr, pixels: Vec<u8>) -> crate::Result<Image> {
+        if ihdr.is_interlaced() {
+            return Err("images are stored non-interlaced".into());
+        }
+        if pixels.len() != ihdr.image_bytes() {
+            return Err("pixel buffer does not match the image size".into());
+        }
+
+        Ok(Image {
+            ihdr,
+            palette: None,
+            transparency: None,
+            pixels,
+        })
+    }
+
+    pub fn with_palette(mut self, palette: Vec<u8>) -> Image {
+        self.palette = Some(palette);
+        self
+    }
+
+    pub fn with_transparency(mut self, transparency: Vec<u8>) -> Image {
+        self.transparency = Some(transparency);
+        self
+    }
+
+    pub fn ihdr(&self) -> &Ihdr {
+        &self.ihdr
+    }
+
+    pub fn palette(&self) -> Option<&[u8]> {
+        self.palette.as_deref()
+    }
+
+    pub fn transparency(&self) -> Option<&[u8]> {
+        self.transparency.as_deref()
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [u8] {
+        &mut self.pixels
+    }
+
+    /// An encoder set up to write this image back with its palette and
+    /// transparency.
+    pub fn encoder(&self) -> Encoder {
+        let mut encoder = Encoder::new(self.ihdr.clone());
+        if let Some(palette) = &self.palette {
+            encoder = encoder.with_palette(palette.clone());
+        }
+        if let Some(transparency) = &self.transparency {
+            encoder = encoder.with_transparency(transparency.clone());
+        }
+        encoder
+    }
+
+    pub fn encode(&self) -> crate::Result<Png> {
+        self.encoder().encode(&self.pixels)
+    }
+
+    /// Every sample of every pixel in scanline order, without row padding.
+    pub fn samples(&self) -> Vec<u16> {
+        let depth = self.ihdr.bit_depth() as usize;
+        let per_row = self.ihdr.width() as usize * self.ihdr.color_type().channels();
+        let mut samples = Vec::with_capacity(per_row * self.ihdr.height() as usize);
+
+        for row in self.pixels.chunks(self.ihdr.row_bytes()) {
+            for i in 0..per_row {
+                samples.push(read_sample(row, i, depth));
+            }
+        }
+        samples
+    }
+
+    /// Rebuilds the pixel buffer from samples laid out as `samples` returns them.
+    pub fn set_samples(&mut self, samples: &[u16]) -> crate::Result<()> {
+        let depth = self.ihdr.bit_depth() as usize;
+        let per_row = self.ihdr.width() as usize * self.ihdr.color_type().channels();
+        if samples.len() != per_row * self.ihdr.height() as usize {
+            return Err("sample count does not match the image size".into());
+        }
+
+        let row_bytes = self.ihdr.row_bytes();
+        for (row, row_samples) in self
+            .pixels
+            .chunks_mut(row_bytes)
+            .zip(samples.chunks(per_row))
+        {
+            row.fill(0);
+            for (i, &sample) in row_samples.iter().enumerate() {
+                write_sample(row, i, depth, sample);
+            }
+        }
+        Ok(())
+    }
+
+    /// Every pixel expanded to RGBA at 8 or 16 bits, with palette and tRNS applied.
+    /// Returns the pixels and their bit depth.
+    pub fn to_rgba(&self) -> crate::Result<(Vec<[u16; 4]>, u8)> {
+        let ihdr = &self.ihdr;
+        let depth = ihdr.bit_depth();
+        let out_depth = if depth == 16 { 16 } else { 8 };
+        let max = if depth == 16 { u16::MAX } else { 255 };
+        let trns = self.transparency.as_deref().unwrap_or(&[]);
+        let key = |i: usize| -> Option<u16> {
+            trns.get(i * 2..i * 2 + 2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        };
+        let scale = |v: u16| -> u16 {
+            match depth {
+                1 => v * 255,
+                2 => v * 85,
+                4 => v * 17,
+                _ => v,
+            }
+        };
+
+        let samples = self.samples();
+        let pixels = match ihdr.color_type() {
+            ColorType::Grayscale => samples
+                .iter()
+                .map(|&g| {
+                    let a = if key(0) == Some(g) { 0 } else { max };
+                    [scale(g), scale(g), scale(g), a]
+                })
+                .collect(),
+            ColorType::Rgb => samples
+                .chunks(3)
+                .map(|p| {
+                    let transparent = trns.len() >= 6
+                        && key(0) == Some(p[0])
+                        && key(1) == Some(p[1])
+                        && key(2) == Some(p[2]);
+                    [p[0], p[1], p[2], if transparent { 0 } else { max }]
+                })
+                .collect(),
+            ColorType::Indexed => {
+                let palette = self.palette.as_deref().unwrap_or(&[]);
+                let mut pixels = Vec::with_capacity(samples.len());
+                for &index in samples.iter() {
+                    let i = index as usize;
+                    let rgb = palette
+                        .get(i * 3..i * 3 + 3)
+                        .ok_or("palette index out of range")?;
+                    let a = trns.get(i).copied().unwrap_or(255);
+                    pixels.push([rgb[0] as u16, rgb[1] as u16, rgb[2] as u16, a as u16]);
+                }
+                pixels
+            }
+            ColorType::GrayscaleAlpha => samples
+                .chunks(2)
+                .map(|p| [p[0], p[0], p[0], p[1]])
+                .collect(),
+            ColorType::Rgba => samples
+                .chunks(4)
+                .map(|p| [p[0], p[1], p[2], p[3]])
+                .collect(),
+        };
+
+        Ok((pixels, out_depth))
+    }
+}
+
+fn read_sample(row: &[u8], index: usize, depth: usize) -> u16 {
+    match depth {
+        16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]),
+        8 => row[index] as u16,
+        _ => {
+            let bit = index * depth;
+            let shift = 8 - depth - bit % 8;
+            ((row[bit / 8] >> shift) & ((1 << depth) - 1) as u8) as u16
+        }
+    }
+}
+
+fn write_sample(row: &mut [u8], index: usize, depth: usize, sample: u16) {
+    match depth {
+        16 => row[index * 2..index * 2 + 2].copy_from_slice(&sample.to_be_bytes()),
+        8 => row[index] = sample as u8,
+        _ => {
+            let bit = index * depth;
+            let shift = 8 - depth - bit % 8;
+            let mask = ((1 << depth) - 1) as u8;
+            row[bit / 8] = (row[bit / 8] & !(mask << shift)) | ((sample as u8 & mask) << shift);
+        }
+    }
+}
+
+fn non_interlaced(ihdr: &Ihdr) -> Ihdr {
+    Ihdr::new(
+        ihdr.width(),
+        ihdr.height(),
+        ihdr.color_type(),
+        ihdr.bit_depth(),
+    )
+    .unwrap()
+}
+
+/// Length of the inflated IDAT stream: every scanline with its filter byte,
+/// pass by pass for an interlaced image.
+fn filtered_len(ihdr: &Ihdr) -> usize {
+    let height = ihdr.height() as usize;
+    if !ihdr.is_interlaced() {
+        return ihdr.image_bytes() + height;
+    }
+
+    let width = ihdr.width() as usize;
+    ADAM7_PASSES
+        .iter()
+        .map(|&(x0, y0, dx, dy)| {
+            let pass_width = (width + dx - 1 - x0) / dx;
+            let pass_height = (height + dy - 1 - y0) / dy;
+            match pass_width {
+                0 => 0,
+                _ => (ihdr.row_bytes_for(pass_width as u32) + 1) * pass_height,
+            }
+        })
+        .sum()
+}
+
+fn deinterlace(ihdr: &Ihdr, data: &[u8]) -> crate::Result<Vec<u8>> {
+    let width = ihdr.width() as usize;
+    let height = ihdr.height() as usize;
+    let bits = ihdr.bits_per_pixel();
+    let row_bytes = ihdr.row_bytes();
+    let mut pixels = vec![0; ihdr.image_bytes()];
+    let mut offset = 0;
+
+    for (x0, y0, dx, dy) in ADAM7_PASSES {
+        let pass_width = (width + dx - 1 - x0) / dx;
+        let pass_height = (height + dy - 1 - y0) / dy;
+        if pass_width == 0 || pass_height == 0 {
+            continue;
+        }
+
+        let pass_row_bytes = ihdr.row_bytes_for(pass_width as u32);
+        let pass_len = (pass_row_bytes + 1) * pass_height;
+        let pass_data = data
+            .get(offset..offset + pass_len)
+            .ok_or("not enough image data")?;
+        let pass = unfilter_image(pass_data, pass_row_bytes, pass_height, ihdr.filter_bpp())?;
+        offset += pass_len;
+
+        for py in 0..pass_height {
+            let src = &pass[py * pass_row_bytes..(py + 1) * pass_row_bytes];
+            let y = y0 + py * dy;
+            let dst = &mut pixels[y * row_bytes..(y + 1) * row_bytes];
+            for px in 0..pass_width {
+                let x = x0 + px * dx;
+                if bits >= 8 {
+                    let n = bits / 8;
+                    dst[x * n..(x + 1) * n].copy_from_slice(&src[px * n..(px + 1) * n]);
+                } else {
+                    write_sample(dst, x, bits, read_sample(src, px, bits));
+                }
+            }
+        }
+    }
+
+    Ok(pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_round_trip() {
+        let ihdr = Ihdr::new(7, 5, ColorType::Rgb, 8).unwrap();
+        let pixels: Vec<u8> = (0..ihdr.image_bytes()).map(|i| (i * 13) as u8).collect();
+        let png = Encoder::new(ihdr).encode(&pixels).unwrap();
+
+        let image = Image::try_from(&png).unwrap();
+        assert_eq!(image.pixels(), &pixels[..]);
+    }
+
+    #[test]
+    fn test_decode_stops_at_the_image_size() {
+        use crate::{chunk::Chunk, chunk_type::ChunkType};
+        use flate2::{write::ZlibEncoder, Compression};
+        use std::{io::Write, str::FromStr};
+
+        let ihdr = Ihdr::new(2, 2, ColorType::Grayscale, 8).unwrap();
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(&vec![0; 10 << 20]).unwrap();
+        let png = Png::from_chunks(vec![
+            ihdr.to_chunk(),
+            Chunk::new(
+                ChunkType::from_str("IDAT").unwrap(),
+                encoder.finish().unwrap(),
+            ),
+        ]);
+        assert!(Image::try_from(&png).is_err());
+    }
+
+    #[test]
+    fn test_samples_round_trip_low_bit_depth() {
+        let ihdr = Ihdr::new(5, 2, ColorType::Grayscale, 2).unwrap();
+        let mut image = Image::new(ihdr, vec![0; 4]).unwrap();
+        let samples: Vec<u16> = vec![0, 1, 2, 3, 0, 3, 2, 1, 0, 1];
+        image.set_samples(&samples).unwrap();
+        assert_eq!(image.samples(), samples);
+        assert_eq!(image.pixels()[0], 0b00011011);
+    }
+
+    #[test]
+    fn test_deinterlace() {
+        // A 3x3 grayscale image where every pixel holds its own index,
+        // interlaced by hand: passes 1, 2 and 3 are empty for such a small image.
+        let ihdr_bytes = [0, 0, 0, 3, 0, 0, 0, 3, 8, 0, 0, 0, 1];
+        let ihdr = Ihdr::try_from(&ihdr_bytes[..]).unwrap();
+        let data = [
+            0, 0, // pass 1: (0,0)
+            0, 2, // pass 4: (2,0)
+            0, 6, 8, // pass 5: (0,2), (2,2)
+            0, 1, // pass 6: (1,0)
+            0, 7, // pass 6: (1,2)
+            0, 3, 4, 5, // pass 7: row 1
+        ];
+        let pixels = deinterlace(&ihdr, &data).unwrap();
+        assert_eq!(pixels, (0..9).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn test_to_rgba_applies_palette_and_transparency() {
+        let ihdr = Ihdr::new(2, 1, ColorType::Indexed, 8).unwrap();
+        let image = Image::new(ihdr, vec![1, 0])
+            .unwrap()
+            .with_palette(vec![10, 20, 30, 40, 50, 60])
+            .with_transparency(vec![0]);
+
+        let (pixels, depth) = image.to_rgba().unwrap();
+        assert_eq!(depth, 8);
+        assert_eq!(pixels, vec![[40, 50, 60, 255], [10, 20, 30, 0]]);
+    }
+}
@@ -0,0 +1,553 @@
+use std::fmt::Display;
+
+use crate::{
+    chunk::Chunk,
+    decoder::Image,
+    ihdr::{ColorType, Ihdr},
+    png::Png,
+    recover, registry,
+};
+
+const TEXT_CHUNKS: [&str; 3] = ["tEXt", "zTXt", "iTXt"];
+
+/// Text chunks larger than this rarely hold ordinary metadata.
+const TEXT_LIMIT: usize = 4096;
+
+/// Chi-square probabilities above which LSB pairs look equalised, worth a
+/// notice and then suspicious. Small prefixes of clean images can reach the
+/// first one by chance.
+const CHI_SQUARE_THRESHOLDS: (f64, f64) = (0.95, 0.995);
+/// RS estimates worth a notice and then suspicious. Flat synthetic images
+/// skew the estimate by several percent.
+const RS_THRESHOLDS: (f64, f64) = (0.1, 0.2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Notice,
+    Suspicious,
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Level::Notice => write!(f, "notice"),
+            Level::Suspicious => write!(f, "suspicious"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub level: Level,
+    pub message: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub findings: Vec<Finding>,
+    /// Highest chi-square embedding probability, and the share of the
+    /// samples it was measured over.
+    pub chi_square: Option<(f64, f64)>,
+    /// Share of the LSBs estimated to carry data by RS analysis.
+    pub rs_rate: Option<f64>,
+}
+
+impl Report {
+    fn push(&mut self, level: Level, message: String) {
+        self.findings.push(Finding { level, message });
+    }
+
+    pub fn is_suspicious(&self) -> bool {
+        self.findings.iter().any(|f| f.level == Level::Suspicious)
+    }
+}
+
+/// Scans the bytes of a PNG file for signs of hidden data.
+pub fn analyze(bytes: &[u8]) -> crate::Result<Report> {
+    let mut report = Report::default();
+
+    // A damaged file is still scanned; the damage itself may come from
+    // hand-editing a chunk without fixing its CRC.
+    let recovery = recover::recover(bytes, true);
+    if recovery.png.chunks().is_empty() {
+        return Err("not a valid PNG file".into());
+    }
+    for damage in recovery.damage.iter() {
+        report.push(
+            Level::Suspicious,
+            format!("offset {}: {}", damage.offset, damage.message),
+        );
+    }
+    let png = recovery.png;
+    let mut chunks = png.chunks();
+
+    // Chunks appended after IEND are checked with the others; whatever does
+    // not even look like a chunk is reported as raw data.
+    let trailing = png.trailing_data();
+    let end = chunks_end(trailing);
+    let after: Vec<Chunk> = [&Png::STANDARD_HEADER[..], &trailing[..end]]
+        .concat()
+        .as_slice()
+        .try_into()
+        .map(|late: Png| late.chunks())
+        .unwrap_or_default();
+    if !after.is_empty() {
+        let types: Vec<String> = after.iter().map(|c| c.chunk_type().to_string()).collect();
+        report.push(Level::Suspicious, format!("chunks after IEND: {types:?}"));
+    }
+    let raw = trailing.len() - after.iter().map(|c| c.as_bytes().len()).sum::<usize>();
+    if raw > 0 {
+        report.push(Level::Suspicious, format!("{raw} bytes of data after IEND"));
+    }
+    chunks.extend(after);
+
+    check_chunk_types(&chunks, &mut report);
+    check_ordering(&chunks, &mut report);
+    check_text(&chunks, &mut report);
+
+    match Image::try_from(&png) {
+        Ok(image) => check_lsbs(&image, &mut report),
+        Err(e) => report.push(Level::Notice, format!("image data cannot be decoded: {e}")),
+    }
+    Ok(report)
+}
+
+/// Offset just past the last complete chunk at the start of `bytes`, found
+/// by walking the chunk lengths while the types look valid.
+fn chunks_end(bytes: &[u8]) -> usize {
+    let mut at = 0;
+    while at + 12 <= bytes.len() {
+        let len = u32::from_be_bytes(bytes[at..at + 4].try_into().unwrap()) as usize;
+        let end = match at.checked_add(12 + len) {
+            Some(end) if end <= bytes.len() => end,
+            _ => break,
+        };
+        if !bytes[at + 4..at + 8].iter().all(u8::is_ascii_alphabetic) {
+            break;
+        }
+        at = end;
+    }
+    at.min(bytes.len())
+}
+
+fn check_chunk_types(chunks: &[Chunk], report: &mut Report) {
+    for chunk in chunks.iter() {
+        let chunk_type = chunk.chunk_type();
+        if registry::lookup(chunk_type).is_some() {
+            continue;
+        }
+        if !chunk_type.is_public() {
+            report.push(
+                Level::Suspicious,
+                format!("private chunk {chunk_type}, {} bytes", chunk.length()),
+            );
+        } else {
+            report.push(
+                Level::Notice,
+                format!("unknown chunk {chunk_type}, {} bytes", chunk.length()),
+            );
+        }
+    }
+}
+
+fn check_ordering(chunks: &[Chunk], report: &mut Report) {
+    for problem in registry::structure_problems(chunks) {
+        report.push(Level::Suspicious, problem);
+    }
+    for problem in registry::placement_problems(chunks) {
+        report.push(Level::Notice, problem);
+    }
+}
+
+fn check_text(chunks: &[Chunk], report: &mut Report) {
+    for chunk in chunks.iter() {
+        let chunk_type = chunk.chunk_type().to_string();
+        if !TEXT_CHUNKS.contains(&&chunk_type[..]) {
+            continue;
+        }
+        if chunk.data().len() > TEXT_LIMIT {
+            report.push(
+                Level::Suspicious,
+                format!("oversized {chunk_type} chunk, {} bytes", chunk.length()),
+            );
+        }
+        if chunk_type == "tEXt" {
+            // Latin-1 text after the keyword, with newlines as the only
+            // control characters.
+            let text = chunk.data().splitn(2, |&b| b == 0).nth(1).unwrap_or(&[]);
+            if text
+                .iter()
+                .any(|&b| (b < 0x20 && b != b'\n') || (0x7f..0xa0).contains(&b))
+            {
+                report.push(Level::Suspicious, "binary data in a tEXt chunk".to_string());
+            }
+        }
+    }
+}
+
+/// The colour samples of 8-bit grayscale and truecolour images, one vector
+/// per channel, ignoring alpha.
+fn color_channels(image: &Image) -> Option<Vec<Vec<u8>>> {
+    let ihdr: &Ihdr = image.ihdr();
+    let colors = match ihdr.color_type() {
+        ColorType::Grayscale | ColorType::GrayscaleAlpha => 1,
+        ColorType::Rgb | ColorType::Rgba => 3,
+        ColorType::Indexed => return None,
+    };
+    if ihdr.bit_depth() != 8 {
+        return None;
+    }
+    let channels = ihdr.color_type().channels();
+    let pixels = image.pixels();
+    Some(
+        (0..colors)
+            .map(|c| pixels.iter().skip(c).step_by(channels).copied().collect())
+            .collect(),
+    )
+}
+
+fn check_lsbs(image: &Image, report: &mut Report) {
+    let Some(channels) = color_channels(image) else {
+        report.push(
+            Level::Notice,
+            "LSB tests only cover 8-bit grayscale and truecolour images".to_string(),
+        );
+        return;
+    };
+
+    // Sequential embedding fills the first samples, so prefixes are tested
+    // as well as the whole image.
+    let interleaved: Vec<u8> = (0..channels[0].len())
+        .flat_map(|i| channels.iter().map(move |c| c[i]))
+        .collect();
+    let chi_square = [0.125, 0.25, 0.5, 1.0]
+        .iter()
+        .filter_map(|&share| {
+            let len = (interleaved.len() as f64 * share) as usize;
+            chi_square_probability(&interleaved[..len]).map(|p| (p, share))
+        })
+        .max_by(|a, b| a.0.total_cmp(&b.0));
+    if let Some((p, share)) = chi_square {
+        if p > CHI_SQUARE_THRESHOLDS.0 {
+            let level = if p > CHI_SQUARE_THRESHOLDS.1 {
+                Level::Suspicious
+            } else {
+                Level::Notice
+            };
+            report.push(
+                level,
+                format!(
+                    "chi-square test: LSB pairs equalised (p = {p:.3}) over the first {:.0}% of samples",
+                    share * 100.0
+                ),
+            );
+        }
+    }
+    report.chi_square = chi_square;
+
+    let rates: Vec<f64> = channels
+        .iter()
+        .filter_map(|c| rs_estimate(c, image.ihdr().width() as usize))
+        .collect();
+    if !rates.is_empty() {
+        let rate = rates.iter().sum::<f64>() / rates.len() as f64;
+        if rate > RS_THRESHOLDS.0 {
+            let level = if rate > RS_THRESHOLDS.1 {
+                Level::Suspicious
+            } else {
+                Level::Notice
+            };
+            report.push(
+                level,
+                format!("RS analysis: about {:.0}% of LSBs carry data", rate * 100.0),
+            );
+        }
+        report.rs_rate = Some(rate);
+    }
+}
+
+/// Westfeld and Pfitzmann's test: embedding random bits equalises the counts
+/// of each pair of values 2k and 2k+1. Returns the probability that the
+/// samples had their LSBs replaced.
+fn chi_square_probability(samples: &[u8]) -> Option<f64> {
+    let mut histogram = [0u64; 256];
+    for &sample in samples.iter() {
+        histogram[sample as usize] += 1;
+    }
+
+    let mut chi = 0.0;
+    let mut categories = 0;
+    for k in 0..128 {
+        let expected = (histogram[2 * k] + histogram[2 * k + 1]) as f64 / 2.0;
+        if expected >= 5.0 {
+            chi += (histogram[2 * k] as f64 - expected).powi(2) / expected;
+            categories += 1;
+        }
+    }
+    if categories < 2 {
+        return None;
+    }
+    Some(1.0 - regularized_gamma((categories - 1) as f64 / 2.0, chi / 2.0))
+}
+
+/// Fridrich's RS analysis over groups of four horizontally adjacent samples
+/// of one channel. Returns the estimated share of samples whose LSB was
+/// replaced.
+fn rs_estimate(samples: &[u8], width: usize) -> Option<f64> {
+    if width < 4 {
+        return None;
+    }
+    let groups: Vec<[i32; 4]> = samples
+        .chunks(width)
+        .flat_map(|row| row.chunks_exact(4))
+        .map(|g| [g[0] as i32, g[1] as i32, g[2] as i32, g[3] as i32])
+        .collect();
+    if groups.len() < 64 {
+        return None;
+    }
+
+    let flipped: Vec<[i32; 4]> = groups.iter().map(|g| g.map(|x| x ^ 1)).collect();
+    let (r, s, r_neg, s_neg) = rs_counts(&groups);
+    let (r1, s1, r1_neg, s1_neg) = rs_counts(&flipped);
+
+    let d0 = r - s;
+    let d1 = r1 - s1;
+    let d0_neg = r_neg - s_neg;
+    let d1_neg = r1_neg - s1_neg;
+
+    let a = 2.0 * (d1 + d0);
+    let b = d0_neg - d1_neg - d1 - 3.0 * d0;
+    let c = d0 - d0_neg;
+    let x = if a.abs() < f64::EPSILON {
+        if b.abs() < f64::EPSILON {
+            return None;
+        }
+        -c / b
+    } else {
+        let discriminant = b * b - 4.0 * a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let roots = [
+            (-b + discriminant.sqrt()) / (2.0 * a),
+            (-b - discriminant.sqrt()) / (2.0 * a),
+        ];
+        if roots[0].abs() < roots[1].abs() {
+            roots[0]
+        } else {
+            roots[1]
+        }
+    };
+    // abs() turns a clamped -0.0 into 0.0.
+    Some((x / (x - 0.5)).clamp(0.0, 1.0).abs())
+}
+
+/// Shares of regular and singular groups under the mask [0, 1, 1, 0] and
+/// its negation.
+fn rs_counts(groups: &[[i32; 4]]) -> (f64, f64, f64, f64) {
+    let smoothness = |g: &[i32; 4]| g.windows(2).map(|w| (w[1] - w[0]).abs()).sum::<i32>();
+    let flip = |x: i32| x ^ 1;
+    let flip_negative = |x: i32| ((x + 1) ^ 1) - 1;
+
+    let mut counts = [0usize; 4];
+    for group in groups.iter() {
+        let f = smoothness(group);
+        for (i, flipper) in [&flip as &dyn Fn(i32) -> i32, &flip_negative]
+            .iter()
+            .enumerate()
+        {
+            let masked = [group[0], flipper(group[1]), flipper(group[2]), group[3]];
+            let g = smoothness(&masked);
+            if g > f {
+                counts[2 * i] += 1;
+            } else if g < f {
+                counts[2 * i + 1] += 1;
+            }
+        }
+    }
+    let n = groups.len() as f64;
+    (
+        counts[0] as f64 / n,
+        counts[1] as f64 / n,
+        counts[2] as f64 / n,
+        counts[3] as f64 / n,
+    )
+}
+
+/// The regularised lower incomplete gamma function P(a, x).
+fn regularized_gamma(a: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    let prefix = (a * x.ln() - x - ln_gamma(a)).exp();
+    if x < a + 1.0 {
+        let mut term = 1.0 / a;
+        let mut sum = term;
+        for n in 1..1000 {
+            term *= x / (a + n as f64);
+            sum += term;
+            if term.abs() < sum.abs() * 1e-15 {
+                break;
+            }
+        }
+        (sum * prefix).min(1.0)
+    } else {
+        // Continued fraction for Q(a, x), evaluated with Lentz's method.
+        let tiny = 1e-300;
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for i in 1..1000 {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < tiny {
+                d = tiny;
+            }
+            c = b + an / c;
+            if c.abs() < tiny {
+                c = tiny;
+            }
+            d = 1.0 / d;
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < 1e-15 {
+                break;
+            }
+        }
+        (1.0 - prefix * h).max(0.0)
+    }
+}
+
+/// Lanczos approximation of ln Γ(x) for x > 0.
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.18009172947146,
+        -86.50532032941677,
+        24.01409824083091,
+        -1.231739572450155,
+        0.1208650973866179e-2,
+        -0.5395239384953e-5,
+    ];
+    let tmp = x + 5.5 - (x + 0.5) * (x + 5.5).ln();
+    let series = COEFFICIENTS
+        .iter()
+        .enumerate()
+        .fold(1.000000000190015, |acc, (i, c)| {
+            acc + c / (x + 1.0 + i as f64)
+        });
+    -tmp + (2.5066282746310005 * series / x).ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{encoder::Encoder, lsb};
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+    use std::str::FromStr;
+
+    /// Flat patches with mild sensor noise, standing in for a photograph.
+    fn natural_image() -> Image {
+        let mut rng = ChaCha20Rng::seed_from_u64(7);
+        let ihdr = Ihdr::new(128, 128, ColorType::Rgb, 8).unwrap();
+        let pixels: Vec<u8> = (0..128 * 128)
+            .flat_map(|i| {
+                let (x, y) = (i % 128 / 16, i / 128 / 16);
+                let base = 40.0 + ((x * 7 + y * 13) % 37) as f64 * 5.0;
+                let mut noise = || {
+                    let n: f64 = (0..4).map(|_| rng.gen_range(-0.5..0.5)).sum();
+                    (base + n * 1.7).round() as u8
+                };
+                [noise(), noise(), noise()]
+            })
+            .collect();
+        Image::new(ihdr, pixels).unwrap()
+    }
+
+    #[test]
+    fn test_ln_gamma_and_regularized_gamma() {
+        assert!((ln_gamma(5.0) - 24f64.ln()).abs() < 1e-9);
+        // P(1, x) = 1 - e^-x
+        assert!((regularized_gamma(1.0, 2.0) - (1.0 - (-2f64).exp())).abs() < 1e-9);
+        assert!((regularized_gamma(3.0, 10.0) - 0.997230604).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_clean_image_is_not_suspicious() {
+        let png = natural_image().encode().unwrap();
+        let report = analyze(&png.as_bytes()).unwrap();
+        assert!(!report.is_suspicious(), "{:?}", report.findings);
+    }
+
+    #[test]
+    fn test_lsb_embedding_is_detected() {
+        let mut image = natural_image();
+        let options = lsb::LsbOptions::default();
+        let mut rng = ChaCha20Rng::seed_from_u64(1);
+        let capacity = lsb::capacity(&image, &options).unwrap();
+        let payload: Vec<u8> = (0..capacity).map(|_| rng.gen()).collect();
+        let chunk_type = crate::chunk_type::ChunkType::from_str("ruSt").unwrap();
+        lsb::embed(&mut image, &chunk_type, &payload, &options).unwrap();
+
+        let report = analyze(&image.encode().unwrap().as_bytes()).unwrap();
+        assert!(report.chi_square.unwrap().0 > CHI_SQUARE_THRESHOLDS.1);
+        assert!(report.rs_rate.unwrap() > 0.5);
+        assert!(report.is_suspicious());
+    }
+
+    #[test]
+    fn test_chunk_findings() {
+        let ihdr = Ihdr::new(4, 4, ColorType::Grayscale, 8).unwrap();
+        let png = Encoder::new(ihdr).encode(&[0; 16]).unwrap();
+        let mut bytes = png.as_bytes();
+        bytes.extend(
+            Chunk::chunk_from_strings("ruSt".to_string(), "hidden".to_string())
+                .unwrap()
+                .as_bytes(),
+        );
+        bytes.extend(b"trailing");
+
+        let report = analyze(&bytes).unwrap();
+        let messages: Vec<&str> = report.findings.iter().map(|f| &f.message[..]).collect();
+        assert!(messages.contains(&"8 bytes of data after IEND"));
+        assert!(messages.contains(&"chunks after IEND: [\"ruSt\"]"));
+        assert!(messages.contains(&"private chunk ruSt, 6 bytes"));
+    }
+
+    #[test]
+    fn test_damaged_file_is_scanned() {
+        let ihdr = Ihdr::new(4, 4, ColorType::Grayscale, 8).unwrap();
+        let mut png = Encoder::new(ihdr).encode(&[0; 16]).unwrap();
+        png.append_chunk(
+            Chunk::chunk_from_strings("ruSt".to_string(), "hidden".to_string()).unwrap(),
+        );
+        let mut bytes = png.as_bytes();
+        // The last byte of the ruSt data, just before IEND.
+        let at = bytes.len() - 12 - 5;
+        bytes[at] ^= 0xff;
+
+        let report = analyze(&bytes).unwrap();
+        let messages: Vec<&str> = report.findings.iter().map(|f| &f.message[..]).collect();
+        let offset = bytes.len() - 12 - 18;
+        assert!(messages.contains(&&format!("offset {offset}: CRC mismatch in ruSt chunk")[..]));
+        assert!(messages.contains(&"private chunk ruSt, 6 bytes"));
+        assert!(analyze(b"not a PNG file at all").is_err());
+    }
+
+    #[test]
+    fn test_text_findings() {
+        let ihdr = Ihdr::new(4, 4, ColorType::Grayscale, 8).unwrap();
+        let mut chunks = Encoder::new(ihdr).encode(&[0; 16]).unwrap().chunks();
+        let text = Chunk::new(
+            crate::chunk_type::ChunkType::from_str("tEXt").unwrap(),
+            [b"Comment\0".to_vec(), vec![1; 5000]].concat(),
+        );
+        chunks.insert(2, text);
+
+        let report = analyze(&Png::from_chunks(chunks).as_bytes()).unwrap();
+        assert!(report.is_suspicious());
+        assert_eq!(report.findings.len(), 2, "{:?}", report.findings);
+    }
+}
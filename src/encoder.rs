@@ -0,0 +1,402 @@
+use std::{fmt::Display, io::Write, str::FromStr};
+
+use flate2::{write::ZlibEncoder, Compression};
+
+use crate::{
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    filter::{filter_image, FilterStrategy},
+    ihdr::{ColorType, Ihdr},
+    message,
+    png::Png,
+    registry,
+};
+
+/// Builds a PNG from raw, non-interlaced pixel data.
+#[derive(Debug, Clone)]
+pub struct Encoder {
+    ihdr: Ihdr,
+    palette: Option<Vec<u8>>,
+    transparency: Option<Vec<u8>>,
+    filter: FilterStrategy,
+    compression: u32,
+    idat_size: usize,
+}
+
+impl Encoder {
+    pub const DEFAULT_IDAT_SIZE: usize = 8192;
+    pub const DEFAULT_COMPRESSION: u32 = 6;
+
+    /// Pixels are always written row by row, so an interlaced header is
+    /// written as non-interlaced.
+    pub fn new(ihdr: Ihdr) -> Encoder {
+        Encoder {
+            ihdr: ihdr.without_interlace(),
+            palette: None,
+            transparency: None,
+            filter: FilterStrategy::Adaptive,
+            compression: Encoder::DEFAULT_COMPRESSION,
+            idat_size: Encoder::DEFAULT_IDAT_SIZE,
+        }
+    }
+
+    /// Sets the PLTE entries as packed RGB triplets. Required for indexed images.
+    pub fn with_palette(mut self, palette: Vec<u8>) -> Encoder {
+        self.palette = Some(palette);
+        self
+    }
+
+    /// Sets the raw contents of a tRNS chunk.
+    pub fn with_transparency(mut self, transparency: Vec<u8>) -> Encoder {
+        self.transparency = Some(transparency);
+        self
+    }
+
+    pub fn with_filter(mut self, filter: FilterStrategy) -> Encoder {
+        self.filter = filter;
+        self
+    }
+
+    /// Zlib compression level, from 0 (store) to 9 (best).
+    pub fn with_compression(mut self, level: u32) -> Encoder {
+        self.compression = level.min(9);
+        self
+    }
+
+    /// Maximum number of data bytes in each IDAT chunk.
+    pub fn with_idat_size(mut self, idat_size: usize) -> Encoder {
+        self.idat_size = idat_size.max(1);
+        self
+    }
+
+    pub fn ihdr(&self) -> &Ihdr {
+        &self.ihdr
+    }
+
+    /// Filters and deflates `pixels` into a zlib stream, without splitting it.
+    pub fn compress(&self, pixels: &[u8]) -> crate::Result<Vec<u8>> {
+        if pixels.len() != self.ihdr.image_bytes() {
+            return Err(format!(
+                "expected {} bytes of pixel data, got {}",
+                self.ihdr.image_bytes(),
+                pixels.len()
+            )
+            .into());
+        }
+
+        let filtered = filter_image(
+            pixels,
+            self.ihdr.row_bytes(),
+            self.ihdr.filter_bpp(),
+            self.filter,
+        );
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(self.compression));
+        encoder.write_all(&filtered)?;
+        Ok(encoder.finish()?)
+    }
+
+    pub fn encode(&self, pixels: &[u8]) -> crate::Result<Png> {
+        let mut chunks = vec![self.ihdr.to_chunk()];
+
+        match (&self.palette, self.ihdr.color_type()) {
+            (Some(palette), _) => {
+                if palette.is_empty() || palette.len() % 3 != 0 || palette.len() > 256 * 3 {
+                    return Err("palette must hold between 1 and 256 RGB entries".into());
+                }
+                chunks.push(Chunk::new(ChunkType::from_str("PLTE")?, palette.clone()));
+            }
+            (None, ColorType::Indexed) => return Err("indexed images need a palette".into()),
+            (None, _) => (),
+        }
+
+        if let Some(transparency) = &self.transparency {
+            chunks.push(Chunk::new(
+                ChunkType::from_str("tRNS")?,
+                transparency.clone(),
+            ));
+        }
+
+        let compressed = self.compress(pixels)?;
+        chunks.extend(split_idat(&compressed, self.idat_size));
+        chunks.push(Chunk::new(ChunkType::from_str("IEND")?, Vec::new()));
+
+        Ok(Png::from_chunks(chunks))
+    }
+}
+
+/// Splits a zlib stream into IDAT chunks holding at most `max_len` bytes each.
+pub fn split_idat(data: &[u8], max_len: usize) -> Vec<Chunk> {
+    let idat = ChunkType::from_str("IDAT").unwrap();
+    if data.is_empty() {
+        return vec![Chunk::new(idat, Vec::new())];
+    }
+
+    data.chunks(max_len.max(1))
+        .map(|part| Chunk::new(idat.clone(), part.to_vec()))
+        .collect()
+}
+
+/// Why `replace_image` left a chunk out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason {
+    /// bKGD, sBIT or hIST written for the old colour type or bit depth.
+    ColorType,
+    /// An unregistered chunk marked unsafe to copy, which the spec says
+    /// must go once critical chunks change.
+    UnsafeToCopy,
+}
+
+impl Display for DropReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            DropReason::ColorType => "it describes the old colour type",
+            DropReason::UnsafeToCopy => "it is unknown and unsafe to copy",
+        };
+        write!(f, "{reason}")
+    }
+}
+
+#[derive(Clone)]
+pub struct Dropped {
+    pub chunk: Chunk,
+    pub reason: DropReason,
+}
+
+/// Puts the image data of `encoded` (IHDR, PLTE, tRNS and IDAT) into `original`,
+/// keeping every other chunk where it was. Chunks whose meaning depends on the
+/// colour type are dropped when the colour type or bit depth changed, and
+/// unknown unsafe-to-copy chunks unless `keep_unsafe` is set; they are
+/// returned alongside the new file. Hidden messages are always kept.
+///
+/// An interlaced animated PNG is refused unless `encoded` is interlaced too,
+/// since its fdAT frames follow the interlace method of IHDR.
+pub fn replace_image(
+    original: &Png,
+    encoded: &Png,
+    keep_unsafe: bool,
+) -> crate::Result<(Png, Vec<Dropped>)> {
+    let old_ihdr = original.ihdr()?;
+    let new_ihdr = encoded.ihdr()?;
+    if old_ihdr.is_interlaced()
+        && !new_ihdr.is_interlaced()
+        && original.chunk_by_type("acTL").is_some()
+    {
+        return Err(
+            "cannot re-encode an interlaced animated PNG, its frames would no longer match IHDR"
+                .into(),
+        );
+    }
+    let same_format = old_ihdr.color_type() == new_ihdr.color_type()
+        && old_ihdr.bit_depth() == new_ihdr.bit_depth();
+
+    let mut palette = encoded.chunk_by_type("PLTE");
+    let mut transparency = encoded.chunk_by_type("tRNS");
+    let mut idats: Vec<Chunk> = encoded
+        .chunks()
+        .into_iter()
+        .filter(|c| c.chunk_type().to_string() == "IDAT")
+        .collect();
+
+    let messages: Vec<usize> = message::messages(original)
+        .into_iter()
+        .flat_map(|m| m.chunks)
+        .collect();
+
+    let mut chunks = Vec::new();
+    let mut dropped = Vec::new();
+
+    for (position, chunk) in original.chunks().into_iter().enumerate() {
+        match &chunk.chunk_type().to_string()[..] {
+            "IHDR" => chunks.push(new_ihdr.to_chunk()),
+            "PLTE" => chunks.extend(palette.take()),
+            "tRNS" => chunks.extend(transparency.take()),
+            "IDAT" => {
+                if !idats.is_empty() {
+                    chunks.extend(palette.take());
+                    chunks.extend(transparency.take());
+                    chunks.append(&mut idats);
+                }
+            }
+            "bKGD" | "sBIT" | "hIST" if !same_format => dropped.push(Dropped {
+                chunk,
+                reason: DropReason::ColorType,
+            }),
+            _ if !keep_unsafe
+                && !messages.contains(&position)
+                && is_unknown_unsafe(chunk.chunk_type()) =>
+            {
+                dropped.push(Dropped {
+                    chunk,
+                    reason: DropReason::UnsafeToCopy,
+                })
+            }
+            _ => chunks.push(chunk),
+        }
+    }
+
+    let mut png = Png::from_chunks(chunks);
+    png.set_trailing_data(original.trailing_data().to_vec());
+    Ok((png, dropped))
+}
+
+fn is_unknown_unsafe(chunk_type: &ChunkType) -> bool {
+    !chunk_type.is_critical()
+        && !chunk_type.is_safe_to_copy()
+        && registry::lookup(chunk_type).is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::ZlibDecoder;
+    use std::io::Read;
+
+    fn idat_stream(png: &Png) -> Vec<u8> {
+        png.chunks()
+            .iter()
+            .filter(|c| c.chunk_type().to_string() == "IDAT")
+            .flat_map(|c| c.data().to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn test_encode_chunk_layout() {
+        let ihdr = Ihdr::new(4, 4, ColorType::Rgb, 8).unwrap();
+        let pixels = vec![200; ihdr.image_bytes()];
+        let png = Encoder::new(ihdr).encode(&pixels).unwrap();
+
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(types.first().unwrap(), "IHDR");
+        assert_eq!(types.last().unwrap(), "IEND");
+        assert!(types.contains(&"IDAT".to_string()));
+    }
+
+    #[test]
+    fn test_encode_round_trips_through_bytes() {
+        let ihdr = Ihdr::new(16, 8, ColorType::Rgba, 8).unwrap();
+        let pixels: Vec<u8> = (0..ihdr.image_bytes()).map(|i| (i * 7) as u8).collect();
+        let png = Encoder::new(ihdr.clone()).encode(&pixels).unwrap();
+
+        let parsed = Png::try_from(&png.as_bytes()[..]).unwrap();
+        let mut inflated = Vec::new();
+        ZlibDecoder::new(&idat_stream(&parsed)[..])
+            .read_to_end(&mut inflated)
+            .unwrap();
+        let raw = crate::filter::unfilter_image(&inflated, ihdr.row_bytes(), 8, 4).unwrap();
+        assert_eq!(raw, pixels);
+    }
+
+    #[test]
+    fn test_encode_splits_idat() {
+        let ihdr = Ihdr::new(64, 64, ColorType::Rgb, 8).unwrap();
+        let pixels: Vec<u8> = (0..ihdr.image_bytes())
+            .map(|i| (i * 31 % 256) as u8)
+            .collect();
+        let png = Encoder::new(ihdr)
+            .with_compression(0)
+            .with_idat_size(1000)
+            .encode(&pixels)
+            .unwrap();
+
+        let idats: Vec<Chunk> = png
+            .chunks()
+            .into_iter()
+            .filter(|c| c.chunk_type().to_string() == "IDAT")
+            .collect();
+        assert!(idats.len() > 1);
+        assert!(idats.iter().all(|c| c.length() <= 1000));
+    }
+
+    #[test]
+    fn test_replace_image_keeps_ancillary_chunks() {
+        let ihdr = Ihdr::new(4, 4, ColorType::Rgb, 8).unwrap();
+        let mut original = Encoder::new(ihdr).encode(&[1; 48]).unwrap();
+        original.append_chunk(
+            Chunk::chunk_from_strings("ruSt".to_string(), "secret".to_string()).unwrap(),
+        );
+
+        let ihdr = Ihdr::new(4, 4, ColorType::Grayscale, 8).unwrap();
+        let encoded = Encoder::new(ihdr).encode(&[1; 16]).unwrap();
+        let (png, dropped) = replace_image(&original, &encoded, false).unwrap();
+
+        assert!(dropped.is_empty());
+        assert_eq!(png.ihdr().unwrap().color_type(), ColorType::Grayscale);
+        assert_eq!(png.idat_data(), encoded.idat_data());
+        assert_eq!(
+            png.chunk_by_type("ruSt").unwrap().data_as_string().unwrap(),
+            "secret"
+        );
+    }
+
+    #[test]
+    fn test_replace_image_drops_unsafe_chunks() {
+        let ihdr = Ihdr::new(2, 2, ColorType::Grayscale, 8).unwrap();
+        let mut original = Encoder::new(ihdr.clone()).encode(&[1; 4]).unwrap();
+        original.append_chunk(Chunk::chunk_from_strings("eXPT".into(), "x".into()).unwrap());
+        original.append_chunk(Chunk::chunk_from_strings("ruSt".into(), "y".into()).unwrap());
+        // A hidden message, kept although its type is unsafe to copy.
+        original.append_chunk(Chunk::chunk_from_strings("ruST".into(), "z".into()).unwrap());
+        let encoded = Encoder::new(ihdr).encode(&[2; 4]).unwrap();
+
+        let (png, dropped) = replace_image(&original, &encoded, false).unwrap();
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].chunk.chunk_type().to_string(), "eXPT");
+        assert_eq!(dropped[0].reason, DropReason::UnsafeToCopy);
+        assert!(png.chunk_by_type("ruSt").is_some());
+        assert!(png.chunk_by_type("ruST").is_some());
+
+        let (png, dropped) = replace_image(&original, &encoded, true).unwrap();
+        assert!(dropped.is_empty());
+        assert!(png.chunk_by_type("eXPT").is_some());
+    }
+
+    #[test]
+    fn test_encode_clears_interlace() {
+        let mut bytes = Ihdr::new(2, 2, ColorType::Grayscale, 8).unwrap().as_bytes();
+        bytes[12] = 1;
+        let ihdr = Ihdr::try_from(&bytes[..]).unwrap();
+        assert!(ihdr.is_interlaced());
+
+        let png = Encoder::new(ihdr).encode(&[1, 2, 3, 4]).unwrap();
+        assert!(!png.ihdr().unwrap().is_interlaced());
+    }
+
+    #[test]
+    fn test_replace_image_refuses_interlaced_animations() {
+        let mut bytes = Ihdr::new(2, 2, ColorType::Grayscale, 8).unwrap().as_bytes();
+        bytes[12] = 1;
+        let interlaced = Ihdr::try_from(&bytes[..]).unwrap();
+        let mut original = Encoder::new(interlaced.clone()).encode(&[1; 4]).unwrap();
+        original.replace_chunk(0, interlaced.to_chunk()).unwrap();
+        let encoded = Encoder::new(interlaced).encode(&[2; 4]).unwrap();
+        assert!(replace_image(&original, &encoded, false).is_ok());
+
+        let actl = ChunkType::from_str("acTL").unwrap();
+        let actl = Chunk::new(actl, vec![0, 0, 0, 1, 0, 0, 0, 0]);
+        original.insert_chunk(1, actl).unwrap();
+        assert!(replace_image(&original, &encoded, false).is_err());
+    }
+
+    #[test]
+    fn test_encode_rejects_wrong_buffer_size() {
+        let ihdr = Ihdr::new(4, 4, ColorType::Grayscale, 8).unwrap();
+        assert!(Encoder::new(ihdr).encode(&[0; 15]).is_err());
+    }
+
+    #[test]
+    fn test_encode_indexed_requires_palette() {
+        let ihdr = Ihdr::new(2, 2, ColorType::Indexed, 8).unwrap();
+        assert!(Encoder::new(ihdr.clone()).encode(&[0; 4]).is_err());
+
+        let png = Encoder::new(ihdr)
+            .with_palette(vec![255, 0, 0])
+            .encode(&[0; 4])
+            .unwrap();
+        assert!(png.chunk_by_type("PLTE").is_some());
+    }
+}
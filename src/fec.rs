@@ -0,0 +1,309 @@
+/// Marks a payload protected by Reed–Solomon coding.
+pub const MAGIC: [u8; 4] = *b"pmC\x01";
+
+/// Parity bytes per block when none are given: up to 8 damaged bytes in
+/// every 255 can be repaired.
+pub const DEFAULT_PARITY: u8 = 16;
+
+const BLOCK_LEN: usize = 255;
+/// Magic plus three copies of the parity count, outvoting a damaged one.
+const HEADER_LEN: usize = 4 + 3;
+
+/// Arithmetic in GF(2^8) with the primitive polynomial x^8+x^4+x^3+x^2+1.
+struct Gf {
+    exp: [u8; 512],
+    log: [u8; 256],
+}
+
+impl Gf {
+    fn new() -> Gf {
+        let mut gf = Gf {
+            exp: [0; 512],
+            log: [0; 256],
+        };
+        let mut x: u16 = 1;
+        for i in 0..255 {
+            gf.exp[i] = x as u8;
+            gf.log[x as usize] = i as u8;
+            x <<= 1;
+            if x & 0x100 != 0 {
+                x ^= 0x11d;
+            }
+        }
+        for i in 255..512 {
+            gf.exp[i] = gf.exp[i - 255];
+        }
+        gf
+    }
+
+    fn mul(&self, a: u8, b: u8) -> u8 {
+        if a == 0 || b == 0 {
+            return 0;
+        }
+        self.exp[self.log[a as usize] as usize + self.log[b as usize] as usize]
+    }
+
+    fn div(&self, a: u8, b: u8) -> u8 {
+        if a == 0 {
+            return 0;
+        }
+        self.exp[(self.log[a as usize] as usize + 255 - self.log[b as usize] as usize) % 255]
+    }
+
+    /// alpha^power, for any power including negative ones.
+    fn pow(&self, power: i32) -> u8 {
+        self.exp[power.rem_euclid(255) as usize]
+    }
+
+    /// Evaluates a polynomial stored highest degree first.
+    fn eval_high_first(&self, poly: &[u8], x: u8) -> u8 {
+        poly.iter().fold(0, |acc, &c| self.mul(acc, x) ^ c)
+    }
+
+    /// Evaluates a polynomial stored lowest degree first.
+    fn eval_low_first(&self, poly: &[u8], x: u8) -> u8 {
+        poly.iter().rev().fold(0, |acc, &c| self.mul(acc, x) ^ c)
+    }
+
+    /// The generator polynomial with roots alpha^0 .. alpha^(parity-1),
+    /// highest degree first.
+    fn generator(&self, parity: usize) -> Vec<u8> {
+        let mut generator = vec![1u8];
+        for i in 0..parity {
+            let root = self.pow(i as i32);
+            let mut next = vec![0u8; generator.len() + 1];
+            for (j, &c) in generator.iter().enumerate() {
+                next[j] ^= c;
+                next[j + 1] ^= self.mul(c, root);
+            }
+            generator = next;
+        }
+        generator
+    }
+
+    fn encode_block(&self, data: &[u8], generator: &[u8]) -> Vec<u8> {
+        let parity = generator.len() - 1;
+        let mut out = data.to_vec();
+        out.resize(data.len() + parity, 0);
+        for i in 0..data.len() {
+            let coef = out[i];
+            if coef != 0 {
+                for (j, &g) in generator.iter().enumerate().skip(1) {
+                    out[i + j] ^= self.mul(g, coef);
+                }
+            }
+        }
+        out[..data.len()].copy_from_slice(data);
+        out
+    }
+
+    fn syndromes(&self, block: &[u8], parity: usize) -> Vec<u8> {
+        (0..parity)
+            .map(|j| self.eval_high_first(block, self.pow(j as i32)))
+            .collect()
+    }
+
+    /// Repairs `block` in place and returns the number of bytes fixed, or
+    /// `None` when there are more errors than the parity can correct.
+    fn correct_block(&self, block: &mut [u8], parity: usize) -> Option<usize> {
+        let syndromes = self.syndromes(block, parity);
+        if syndromes.iter().all(|&s| s == 0) {
+            return Some(0);
+        }
+
+        // Berlekamp–Massey, with polynomials stored lowest degree first.
+        let mut locator = vec![1u8];
+        let mut previous = vec![1u8];
+        let mut errors = 0;
+        let mut shift = 1;
+        let mut last_discrepancy = 1u8;
+        for n in 0..parity {
+            let discrepancy = (1..=errors).fold(syndromes[n], |d, i| {
+                d ^ self.mul(*locator.get(i).unwrap_or(&0), syndromes[n - i])
+            });
+            if discrepancy == 0 {
+                shift += 1;
+                continue;
+            }
+            let scale = self.div(discrepancy, last_discrepancy);
+            let mut next = locator.clone();
+            next.resize(next.len().max(previous.len() + shift), 0);
+            for (i, &c) in previous.iter().enumerate() {
+                next[i + shift] ^= self.mul(scale, c);
+            }
+            if 2 * errors <= n {
+                previous = std::mem::replace(&mut locator, next);
+                errors = n + 1 - errors;
+                last_discrepancy = discrepancy;
+                shift = 1;
+            } else {
+                locator = next;
+                shift += 1;
+            }
+        }
+        locator.truncate(errors + 1);
+        if 2 * errors > parity {
+            return None;
+        }
+
+        // Chien search: an error at power k makes locator(alpha^-k) zero.
+        let n = block.len();
+        let powers: Vec<usize> = (0..n)
+            .filter(|&k| self.eval_low_first(&locator, self.pow(-(k as i32))) == 0)
+            .collect();
+        if powers.len() != errors {
+            return None;
+        }
+
+        // Forney: e = X * omega(X^-1) / locator'(X^-1).
+        let mut evaluator = vec![0u8; parity];
+        for (i, &s) in syndromes.iter().enumerate() {
+            for (j, &l) in locator.iter().enumerate() {
+                if i + j < parity {
+                    evaluator[i + j] ^= self.mul(s, l);
+                }
+            }
+        }
+        let derivative: Vec<u8> = locator
+            .iter()
+            .enumerate()
+            .skip(1)
+            .map(|(i, &c)| if i % 2 == 1 { c } else { 0 })
+            .collect();
+
+        for &k in powers.iter() {
+            let x = self.pow(k as i32);
+            let x_inv = self.pow(-(k as i32));
+            let denominator = self.eval_low_first(&derivative, x_inv);
+            if denominator == 0 {
+                return None;
+            }
+            let magnitude = self.div(
+                self.mul(x, self.eval_low_first(&evaluator, x_inv)),
+                denominator,
+            );
+            block[n - 1 - k] ^= magnitude;
+        }
+
+        if self.syndromes(block, parity).iter().any(|&s| s != 0) {
+            return None;
+        }
+        Some(errors)
+    }
+}
+
+pub fn is_protected(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+/// Adds `parity` Reed–Solomon bytes to every block of up to 255 bytes, so
+/// `recover` can repair up to `parity / 2` damaged bytes per block. The
+/// magic is left unprotected, since it is what tells readers to decode.
+pub fn protect(payload: &[u8], parity: u8) -> crate::Result<Vec<u8>> {
+    if !(2..=128).contains(&parity) {
+        return Err("parity must be between 2 and 128 bytes per block".into());
+    }
+    let gf = Gf::new();
+    let generator = gf.generator(parity as usize);
+
+    let stream: Vec<u8> = (payload.len() as u32)
+        .to_be_bytes()
+        .iter()
+        .chain(payload.iter())
+        .copied()
+        .collect();
+
+    let mut out = MAGIC.to_vec();
+    out.extend([parity; 3]);
+    for data in stream.chunks(BLOCK_LEN - parity as usize) {
+        out.extend(gf.encode_block(data, &generator));
+    }
+    Ok(out)
+}
+
+/// Undoes `protect`, returning the payload and how many bytes were repaired.
+pub fn recover(data: &[u8]) -> crate::Result<(Vec<u8>, usize)> {
+    if !is_protected(data) || data.len() < HEADER_LEN {
+        return Err("payload is not error corrected".into());
+    }
+    let votes = &data[4..HEADER_LEN];
+    let parity = if votes[0] == votes[1] || votes[0] == votes[2] {
+        votes[0]
+    } else if votes[1] == votes[2] {
+        votes[1]
+    } else {
+        return Err("error correction header is damaged".into());
+    } as usize;
+    if !(2..=128).contains(&parity) {
+        return Err("error correction header is damaged".into());
+    }
+
+    let gf = Gf::new();
+    let mut stream = Vec::new();
+    let mut corrected = 0;
+    for (i, block) in data[HEADER_LEN..].chunks(BLOCK_LEN).enumerate() {
+        if block.len() <= parity {
+            return Err("truncated error corrected payload".into());
+        }
+        let mut block = block.to_vec();
+        corrected += gf
+            .correct_block(&mut block, parity)
+            .ok_or_else(|| format!("block {i} has too many errors to correct"))?;
+        stream.extend(&block[..block.len() - parity]);
+    }
+
+    if stream.len() < 4 {
+        return Err("truncated error corrected payload".into());
+    }
+    let len = u32::from_be_bytes(stream[..4].try_into().unwrap()) as usize;
+    if stream.len() - 4 != len {
+        return Err("error corrected payload has the wrong length".into());
+    }
+    Ok((stream[4..].to_vec(), corrected))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protect_and_recover() {
+        let payload: Vec<u8> = (0..600).map(|i| (i * 31) as u8).collect();
+        let protected = protect(&payload, 16).unwrap();
+        assert!(is_protected(&protected));
+        assert_eq!(recover(&protected).unwrap(), (payload, 0));
+    }
+
+    #[test]
+    fn test_recover_corrects_errors() {
+        let payload: Vec<u8> = (0..600).map(|i| (i * 7) as u8).collect();
+        let mut protected = protect(&payload, 16).unwrap();
+        // Eight errors in the first block, three in the last one.
+        for i in [7, 20, 55, 100, 101, 180, 200, 261] {
+            protected[i] ^= 0xa5;
+        }
+        let last = protected.len() - 1;
+        for i in [last, last - 40, last - 41] {
+            protected[i] = protected[i].wrapping_add(1);
+        }
+        protected[5] = 0;
+
+        assert_eq!(recover(&protected).unwrap(), (payload, 11));
+    }
+
+    #[test]
+    fn test_too_many_errors_are_reported() {
+        let mut protected = protect(b"short message", 4).unwrap();
+        for byte in protected[HEADER_LEN..HEADER_LEN + 3].iter_mut() {
+            *byte ^= 0xff;
+        }
+        assert!(recover(&protected).is_err());
+    }
+
+    #[test]
+    fn test_parity_is_checked() {
+        assert!(protect(b"x", 1).is_err());
+        assert!(protect(b"x", 129).is_err());
+        assert_eq!(recover(&protect(b"", 2).unwrap()).unwrap().0, b"");
+    }
+}
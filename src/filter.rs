@@ -0,0 +1,214 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterType {
+    None = 0,
+    Sub = 1,
+    Up = 2,
+    Average = 3,
+    Paeth = 4,
+}
+
+impl TryFrom<u8> for FilterType {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(FilterType::None),
+            1 => Ok(FilterType::Sub),
+            2 => Ok(FilterType::Up),
+            3 => Ok(FilterType::Average),
+            4 => Ok(FilterType::Paeth),
+            _ => Err("invalid filter type"),
+        }
+    }
+}
+
+impl FilterType {
+    pub const ALL: [FilterType; 5] = [
+        FilterType::None,
+        FilterType::Sub,
+        FilterType::Up,
+        FilterType::Average,
+        FilterType::Paeth,
+    ];
+}
+
+/// How the encoder picks a filter for every scanline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterStrategy {
+    Fixed(FilterType),
+    /// Try every filter and keep the one with the smallest sum of absolute
+    /// differences, the heuristic recommended by the spec.
+    Adaptive,
+}
+
+fn paeth_predictor(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Filters `row` against the previous unfiltered scanline `prev`, writing the
+/// filter type byte followed by the filtered bytes into `out`.
+pub fn filter_row(filter: FilterType, row: &[u8], prev: &[u8], bpp: usize, out: &mut Vec<u8>) {
+    out.push(filter as u8);
+    for i in 0..row.len() {
+        let a = if i >= bpp { row[i - bpp] } else { 0 };
+        let b = prev[i];
+        let c = if i >= bpp { prev[i - bpp] } else { 0 };
+
+        let predicted = match filter {
+            FilterType::None => 0,
+            FilterType::Sub => a,
+            FilterType::Up => b,
+            FilterType::Average => ((a as u16 + b as u16) / 2) as u8,
+            FilterType::Paeth => paeth_predictor(a, b, c),
+        };
+        out.push(row[i].wrapping_sub(predicted));
+    }
+}
+
+/// Reverses `filter_row` in place, `prev` being the already reconstructed
+/// previous scanline.
+pub fn unfilter_row(filter: FilterType, row: &mut [u8], prev: &[u8], bpp: usize) {
+    for i in 0..row.len() {
+        let a = if i >= bpp { row[i - bpp] } else { 0 };
+        let b = prev[i];
+        let c = if i >= bpp { prev[i - bpp] } else { 0 };
+
+        let predicted = match filter {
+            FilterType::None => 0,
+            FilterType::Sub => a,
+            FilterType::Up => b,
+            FilterType::Average => ((a as u16 + b as u16) / 2) as u8,
+            FilterType::Paeth => paeth_predictor(a, b, c),
+        };
+        row[i] = row[i].wrapping_add(predicted);
+    }
+}
+
+fn filtered_cost(filtered: &[u8]) -> u64 {
+    filtered[1..]
+        .iter()
+        .map(|&byte| (byte as i8).unsigned_abs() as u64)
+        .sum()
+}
+
+/// Filters a whole image of `row_bytes` wide scanlines.
+pub fn filter_image(
+    pixels: &[u8],
+    row_bytes: usize,
+    bpp: usize,
+    strategy: FilterStrategy,
+) -> Vec<u8> {
+    let mut out = Vec::with_capacity(pixels.len() + pixels.len() / row_bytes.max(1));
+    let zero_row = vec![0; row_bytes];
+    let mut prev: &[u8] = &zero_row;
+
+    for row in pixels.chunks(row_bytes) {
+        match strategy {
+            FilterStrategy::Fixed(filter) => filter_row(filter, row, prev, bpp, &mut out),
+            FilterStrategy::Adaptive => {
+                let mut best: Option<(u64, Vec<u8>)> = None;
+                for filter in FilterType::ALL {
+                    let mut candidate = Vec::with_capacity(row_bytes + 1);
+                    filter_row(filter, row, prev, bpp, &mut candidate);
+                    let cost = filtered_cost(&candidate);
+                    if best.as_ref().is_none_or(|(best_cost, _)| cost < *best_cost) {
+                        best = Some((cost, candidate));
+                    }
+                }
+                out.extend(best.unwrap().1);
+            }
+        }
+        prev = row;
+    }
+
+    out
+}
+
+/// Reverses `filter_image`, returning the raw scanlines without filter bytes.
+pub fn unfilter_image(
+    data: &[u8],
+    row_bytes: usize,
+    height: usize,
+    bpp: usize,
+) -> Result<Vec<u8>, &'static str> {
+    let needed = (row_bytes + 1)
+        .checked_mul(height)
+        .ok_or("image is too large")?;
+    if data.len() < needed {
+        return Err("not enough image data");
+    }
+
+    let mut out: Vec<u8> = Vec::with_capacity(needed - height);
+    let zero_row = vec![0; row_bytes];
+
+    for (i, line) in data.chunks(row_bytes + 1).take(height).enumerate() {
+        let filter = FilterType::try_from(line[0])?;
+        let mut row = line[1..].to_vec();
+        let prev = if i == 0 {
+            &zero_row[..]
+        } else {
+            &out[(i - 1) * row_bytes..i * row_bytes]
+        };
+        unfilter_row(filter, &mut row, prev, bpp);
+        out.extend(row);
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_pixels() -> Vec<u8> {
+        (0..60u32).map(|i| (i * 37 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_filter_round_trip() {
+        let pixels = testing_pixels();
+        for filter in FilterType::ALL {
+            let filtered = filter_image(&pixels, 12, 3, FilterStrategy::Fixed(filter));
+            assert_eq!(filtered.len(), 65);
+            assert!(filtered.chunks(13).all(|row| row[0] == filter as u8));
+            let unfiltered = unfilter_image(&filtered, 12, 5, 3).unwrap();
+            assert_eq!(unfiltered, pixels);
+        }
+    }
+
+    #[test]
+    fn test_adaptive_filter_round_trip() {
+        let pixels = testing_pixels();
+        let filtered = filter_image(&pixels, 12, 3, FilterStrategy::Adaptive);
+        let unfiltered = unfilter_image(&filtered, 12, 5, 3).unwrap();
+        assert_eq!(unfiltered, pixels);
+    }
+
+    #[test]
+    fn test_adaptive_filter_prefers_sub_on_gradient() {
+        let pixels: Vec<u8> = (0..16).collect();
+        let filtered = filter_image(&pixels, 16, 1, FilterStrategy::Adaptive);
+        assert_eq!(filtered[0], FilterType::Sub as u8);
+    }
+
+    #[test]
+    fn test_unfilter_rejects_overflowing_size() {
+        assert!(unfilter_image(&[0; 16], usize::MAX / 2, 3, 1).is_err());
+    }
+
+    #[test]
+    fn test_unfilter_rejects_bad_filter_byte() {
+        assert!(unfilter_image(&[9, 0, 0], 2, 1, 1).is_err());
+    }
+}
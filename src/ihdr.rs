@@ -0,0 +1,250 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::{chunk::Chunk, chunk_type::ChunkType};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorType {
+    Grayscale = 0,
+    Rgb = 2,
+    Indexed = 3,
+    GrayscaleAlpha = 4,
+    Rgba = 6,
+}
+
+impl TryFrom<u8> for ColorType {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ColorType::Grayscale),
+            2 => Ok(ColorType::Rgb),
+            3 => Ok(ColorType::Indexed),
+            4 => Ok(ColorType::GrayscaleAlpha),
+            6 => Ok(ColorType::Rgba),
+            _ => Err("invalid colour type"),
+        }
+    }
+}
+
+impl Display for ColorType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ColorType::Grayscale => "grayscale",
+            ColorType::Rgb => "rgb",
+            ColorType::Indexed => "indexed",
+            ColorType::GrayscaleAlpha => "grayscale+alpha",
+            ColorType::Rgba => "rgba",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl ColorType {
+    /// Number of samples stored for every pixel.
+    pub fn channels(&self) -> usize {
+        match self {
+            ColorType::Grayscale | ColorType::Indexed => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        }
+    }
+
+    /// Bit depths the spec allows for this colour type.
+    pub fn allowed_bit_depths(&self) -> &'static [u8] {
+        match self {
+            ColorType::Grayscale => &[1, 2, 4, 8, 16],
+            ColorType::Indexed => &[1, 2, 4, 8],
+            ColorType::Rgb | ColorType::GrayscaleAlpha | ColorType::Rgba => &[8, 16],
+        }
+    }
+}
+
+/// The contents of an IHDR chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ihdr {
+    width: u32,
+    height: u32,
+    bit_depth: u8,
+    color_type: ColorType,
+    interlace: bool,
+}
+
+impl TryFrom<&[u8]> for Ihdr {
+    type Error = &'static str;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        if value.len() != 13 {
+            return Err("IHDR must be 13 bytes long");
+        }
+
+        let width = u32::from_be_bytes([value[0], value[1], value[2], value[3]]);
+        let height = u32::from_be_bytes([value[4], value[5], value[6], value[7]]);
+        let color_type = ColorType::try_from(value[9])?;
+
+        if value[10] != 0 {
+            return Err("unknown compression method");
+        }
+        if value[11] != 0 {
+            return Err("unknown filter method");
+        }
+        let interlace = match value[12] {
+            0 => false,
+            1 => true,
+            _ => return Err("unknown interlace method"),
+        };
+
+        let mut ihdr = Ihdr::new(width, height, color_type, value[8])?;
+        ihdr.interlace = interlace;
+        Ok(ihdr)
+    }
+}
+
+impl Ihdr {
+    /// The largest raw pixel buffer accepted, so that a tiny file cannot claim
+    /// an image too big to allocate or whose size overflows.
+    pub const MAX_IMAGE_BYTES: usize = 1 << 30;
+
+    pub fn new(
+        width: u32,
+        height: u32,
+        color_type: ColorType,
+        bit_depth: u8,
+    ) -> Result<Ihdr, &'static str> {
+        if width == 0 || height == 0 {
+            return Err("image dimensions must be non-zero");
+        }
+        if !color_type.allowed_bit_depths().contains(&bit_depth) {
+            return Err("bit depth not allowed for colour type");
+        }
+        let bits_per_pixel = color_type.channels() * bit_depth as usize;
+        let image_bytes = (width as usize)
+            .checked_mul(bits_per_pixel)
+            .map(|bits| bits.div_ceil(8) + 1)
+            .and_then(|row| row.checked_mul(height as usize));
+        if image_bytes.is_none_or(|bytes| bytes > Ihdr::MAX_IMAGE_BYTES) {
+            return Err("image is too large");
+        }
+
+        Ok(Ihdr {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            interlace: false,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn bit_depth(&self) -> u8 {
+        self.bit_depth
+    }
+
+    pub fn color_type(&self) -> ColorType {
+        self.color_type
+    }
+
+    pub fn is_interlaced(&self) -> bool {
+        self.interlace
+    }
+
+    /// The same header with the interlace flag cleared.
+    pub fn without_interlace(mut self) -> Ihdr {
+        self.interlace = false;
+        self
+    }
+
+    pub fn bits_per_pixel(&self) -> usize {
+        self.color_type.channels() * self.bit_depth as usize
+    }
+
+    /// Distance in bytes between a byte and the matching byte of the previous
+    /// pixel, as used by the filter algorithms (never less than 1).
+    pub fn filter_bpp(&self) -> usize {
+        std::cmp::max(1, self.bits_per_pixel() / 8)
+    }
+
+    /// Length of one unfiltered scanline of `width` pixels, without the
+    /// leading filter byte.
+    pub fn row_bytes_for(&self, width: u32) -> usize {
+        (width as usize * self.bits_per_pixel()).div_ceil(8)
+    }
+
+    pub fn row_bytes(&self) -> usize {
+        self.row_bytes_for(self.width)
+    }
+
+    /// Size of the raw, unfiltered pixel buffer of a non-interlaced image,
+    /// at most `MAX_IMAGE_BYTES`.
+    pub fn image_bytes(&self) -> usize {
+        self.row_bytes() * self.height as usize
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        self.width
+            .to_be_bytes()
+            .iter()
+            .chain(self.height.to_be_bytes().iter())
+            .copied()
+            .chain([
+                self.bit_depth,
+                self.color_type as u8,
+                0,
+                0,
+                self.interlace as u8,
+            ])
+            .collect()
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new(ChunkType::from_str("IHDR").unwrap(), self.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ihdr_round_trip() {
+        let ihdr = Ihdr::new(50, 20, ColorType::Rgba, 8).unwrap();
+        let parsed = Ihdr::try_from(&ihdr.as_bytes()[..]).unwrap();
+        assert_eq!(ihdr, parsed);
+        assert_eq!(parsed.as_bytes().len(), 13);
+    }
+
+    #[test]
+    fn test_ihdr_rejects_bad_bit_depth() {
+        assert!(Ihdr::new(1, 1, ColorType::Rgb, 4).is_err());
+        assert!(Ihdr::new(1, 1, ColorType::Indexed, 16).is_err());
+        assert!(Ihdr::new(0, 1, ColorType::Grayscale, 8).is_err());
+    }
+
+    #[test]
+    fn test_ihdr_rejects_huge_images() {
+        let mut bytes = Ihdr::new(1, 1, ColorType::Rgba, 16).unwrap().as_bytes();
+        bytes[0..8].copy_from_slice(&[0x7f, 0xff, 0xff, 0xff, 0x7f, 0xff, 0xff, 0xff]);
+        assert!(Ihdr::try_from(&bytes[..]).is_err());
+        assert!(Ihdr::new(1 << 20, 1 << 20, ColorType::Grayscale, 1).is_err());
+        assert!(Ihdr::new(8192, 8192, ColorType::Rgba, 8).is_ok());
+    }
+
+    #[test]
+    fn test_ihdr_row_bytes() {
+        let ihdr = Ihdr::new(10, 1, ColorType::Grayscale, 1).unwrap();
+        assert_eq!(ihdr.row_bytes(), 2);
+        assert_eq!(ihdr.filter_bpp(), 1);
+
+        let ihdr = Ihdr::new(10, 3, ColorType::Rgba, 16).unwrap();
+        assert_eq!(ihdr.row_bytes(), 80);
+        assert_eq!(ihdr.filter_bpp(), 8);
+        assert_eq!(ihdr.image_bytes(), 240);
+    }
+}
@@ -0,0 +1,26 @@
+pub mod apng;
+pub mod attachment;
+pub mod chunk;
+pub mod chunk_type;
+pub mod cli;
+pub mod compress;
+pub mod crypto;
+pub mod decoder;
+pub mod detect;
+pub mod encoder;
+pub mod fec;
+pub mod filter;
+pub mod ihdr;
+pub mod lsb;
+pub mod message;
+pub mod mng;
+pub mod optimize;
+pub mod png;
+pub mod recover;
+pub mod registry;
+pub mod signature;
+pub mod split;
+pub mod strip;
+
+pub type Error = Box<dyn std::error::Error>;
+pub type Result<T> = std::result::Result<T, Error>;
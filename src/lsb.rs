@@ -0,0 +1,414 @@
+use std::str::FromStr;
+
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use sha2::{Digest, Sha256};
+
+use crate::{chunk_type::ChunkType, decoder::Image, ihdr::ColorType};
+
+/// Which samples of a pixel carry payload bits. `r`, `g` and `b` select the
+/// colour samples (all three mean the grey sample of grayscale images) and
+/// `a` the alpha sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelMask {
+    color: [bool; 3],
+    alpha: bool,
+}
+
+impl FromStr for ChannelMask {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut mask = ChannelMask {
+            color: [false; 3],
+            alpha: false,
+        };
+        for c in s.chars() {
+            match c.to_ascii_lowercase() {
+                'r' => mask.color[0] = true,
+                'g' => mask.color[1] = true,
+                'b' => mask.color[2] = true,
+                'a' => mask.alpha = true,
+                _ => return Err("channels must be a combination of r, g, b and a"),
+            }
+        }
+        if mask.color == [false; 3] && !mask.alpha {
+            return Err("at least one channel is needed");
+        }
+        Ok(mask)
+    }
+}
+
+impl Default for ChannelMask {
+    fn default() -> Self {
+        ChannelMask {
+            color: [true; 3],
+            alpha: false,
+        }
+    }
+}
+
+impl ChannelMask {
+    /// Positions, within one pixel, of the samples selected for `color_type`.
+    pub fn sample_indices(&self, color_type: ColorType) -> Vec<usize> {
+        let gray = self.color.contains(&true);
+        match color_type {
+            ColorType::Grayscale if gray => vec![0],
+            ColorType::GrayscaleAlpha => [(gray, 0), (self.alpha, 1)]
+                .iter()
+                .filter(|(used, _)| *used)
+                .map(|(_, i)| *i)
+                .collect(),
+            ColorType::Rgb | ColorType::Rgba => {
+                let mut indices: Vec<usize> = (0..3).filter(|&i| self.color[i]).collect();
+                if self.alpha && color_type == ColorType::Rgba {
+                    indices.push(3);
+                }
+                indices
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LsbOptions {
+    /// Low bits of every selected sample that carry payload, 1 to 8.
+    pub bits: u8,
+    pub channels: ChannelMask,
+    /// Passphrase that scatters the payload over pseudo-randomly chosen
+    /// samples instead of filling them in order.
+    pub key: Option<String>,
+}
+
+impl Default for LsbOptions {
+    fn default() -> Self {
+        LsbOptions {
+            bits: 1,
+            channels: ChannelMask::default(),
+            key: None,
+        }
+    }
+}
+
+/// Magic (the chunk type the message was filed under) plus payload length.
+const HEADER_LEN: usize = 8;
+
+fn carrier_samples(image: &Image, options: &LsbOptions) -> crate::Result<Vec<usize>> {
+    let ihdr = image.ihdr();
+    if ihdr.color_type() == ColorType::Indexed {
+        return Err("LSB embedding needs a grayscale or truecolour image".into());
+    }
+    if options.bits == 0 || options.bits > 8 || options.bits > ihdr.bit_depth() {
+        return Err("bits per sample must be between 1 and min(8, bit depth)".into());
+    }
+
+    let channels = ihdr.color_type().channels();
+    let selected = options.channels.sample_indices(ihdr.color_type());
+    if selected.is_empty() {
+        return Err("no selected channel exists in this image".into());
+    }
+
+    let pixels = ihdr.width() as usize * ihdr.height() as usize;
+    let mut carriers: Vec<usize> = (0..pixels)
+        .flat_map(|p| selected.iter().map(move |&c| p * channels + c))
+        .collect();
+
+    if let Some(key) = &options.key {
+        if carriers.len() > u32::MAX as usize {
+            return Err("the image has too many samples for keyed embedding".into());
+        }
+        shuffle(&mut carriers, &mut keyed_rng(key));
+    }
+    Ok(carriers)
+}
+
+fn keyed_rng(key: &str) -> ChaCha20Rng {
+    let mut hasher = Sha256::new();
+    hasher.update(b"pngme lsb scatter\0");
+    hasher.update(key.as_bytes());
+    ChaCha20Rng::from_seed(hasher.finalize().into())
+}
+
+/// Fisher-Yates shuffle drawing only `next_u32` from the ChaCha stream, so
+/// the order of embedded bits does not depend on how the rand crate happens
+/// to sample ranges in a given version.
+fn shuffle(items: &mut [usize], rng: &mut ChaCha20Rng) {
+    for i in (1..items.len()).rev() {
+        let j = uniform_below(rng, i as u64 + 1) as usize;
+        items.swap(i, j);
+    }
+}
+
+/// A uniform number below `bound`, rejecting draws past the last whole
+/// multiple of `bound`.
+fn uniform_below(rng: &mut ChaCha20Rng, bound: u64) -> u64 {
+    let limit = (1u64 << 32) - (1u64 << 32) % bound;
+    loop {
+        let draw = rng.next_u32() as u64;
+        if draw < limit {
+            return draw % bound;
+        }
+    }
+}
+
+/// Number of payload bytes `embed` can hide in `image`.
+pub fn capacity(image: &Image, options: &LsbOptions) -> crate::Result<usize> {
+    let bits = carrier_samples(image, options)?.len() * options.bits as usize;
+    Ok((bits / 8).saturating_sub(HEADER_LEN))
+}
+
+fn write_bits(samples: &mut [u16], carriers: &[usize], bits: u8, data: &[u8]) {
+    let mask = (1u16 << bits) - 1;
+    let mut stream = data
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1));
+
+    for &index in carriers.iter() {
+        let mut value = 0u16;
+        let mut written = 0;
+        for _ in 0..bits {
+            match stream.next() {
+                Some(bit) => {
+                    value = (value << 1) | bit as u16;
+                    written += 1;
+                }
+                None => break,
+            }
+        }
+        if written == 0 {
+            return;
+        }
+        // A partially filled last sample keeps its remaining low bits.
+        let shift = bits - written;
+        let keep = (1u16 << shift) - 1;
+        samples[index] = (samples[index] & !mask) | (value << shift) | (samples[index] & keep);
+    }
+}
+
+fn read_bits(samples: &[u16], carriers: &[usize], bits: u8, len: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(len);
+    let mut byte = 0u8;
+    let mut filled = 0;
+
+    for &index in carriers.iter() {
+        for i in (0..bits).rev() {
+            byte = (byte << 1) | ((samples[index] >> i) & 1) as u8;
+            filled += 1;
+            if filled == 8 {
+                out.push(byte);
+                if out.len() == len {
+                    return out;
+                }
+                byte = 0;
+                filled = 0;
+            }
+        }
+    }
+    out
+}
+
+/// Hides `payload` in the low bits of the samples of `image`, preceded by a
+/// header made of `chunk_type` and the payload length.
+pub fn embed(
+    image: &mut Image,
+    chunk_type: &ChunkType,
+    payload: &[u8],
+    options: &LsbOptions,
+) -> crate::Result<()> {
+    let available = capacity(image, options)?;
+    if payload.len() > available {
+        return Err(format!(
+            "payload is {} bytes but the image only holds {available}",
+            payload.len()
+        )
+        .into());
+    }
+
+    let data: Vec<u8> = chunk_type
+        .bytes()
+        .iter()
+        .chain((payload.len() as u32).to_be_bytes().iter())
+        .chain(payload.iter())
+        .copied()
+        .collect();
+
+    let carriers = carrier_samples(image, options)?;
+    let mut samples = image.samples();
+    write_bits(&mut samples, &carriers, options.bits, &data);
+    image.set_samples(&samples)
+}
+
+/// Reads back a payload hidden by `embed` under `chunk_type`.
+pub fn extract(
+    image: &Image,
+    chunk_type: &ChunkType,
+    options: &LsbOptions,
+) -> crate::Result<Vec<u8>> {
+    let carriers = carrier_samples(image, options)?;
+    let samples = image.samples();
+
+    let header = read_bits(&samples, &carriers, options.bits, HEADER_LEN);
+    if header.len() < HEADER_LEN || header[..4] != chunk_type.bytes() {
+        return Err(format!("no {chunk_type} message hidden in the pixels").into());
+    }
+
+    let len = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    if len > capacity(image, options)? {
+        return Err("hidden message length exceeds the image capacity".into());
+    }
+
+    let data = read_bits(&samples, &carriers, options.bits, HEADER_LEN + len);
+    Ok(data[HEADER_LEN..].to_vec())
+}
+
+/// Whether `image` seems to hold a message embedded without a key, in one to
+/// eight low bits of the colour samples, with or without alpha. Messages
+/// scattered with a key cannot be told apart from noise.
+pub fn has_unkeyed_message(image: &Image) -> bool {
+    let samples = image.samples();
+    let masks = [
+        ChannelMask::default(),
+        ChannelMask::from_str("rgba").unwrap(),
+    ];
+    masks.into_iter().any(|channels| {
+        (1..=8).any(|bits| {
+            let options = LsbOptions {
+                bits,
+                channels,
+                key: None,
+            };
+            let Ok(carriers) = carrier_samples(image, &options) else {
+                return false;
+            };
+            let header = read_bits(&samples, &carriers, bits, HEADER_LEN);
+            header.len() == HEADER_LEN
+                && ChunkType::try_from(<[u8; 4]>::try_from(&header[..4]).unwrap()).is_ok()
+                && u32::from_be_bytes(header[4..].try_into().unwrap()) as usize
+                    <= capacity(image, &options).unwrap_or(0)
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ihdr::Ihdr;
+
+    fn testing_image(color_type: ColorType, bit_depth: u8) -> Image {
+        let ihdr = Ihdr::new(16, 16, color_type, bit_depth).unwrap();
+        let pixels: Vec<u8> = (0..ihdr.image_bytes()).map(|i| (i * 7) as u8).collect();
+        Image::new(ihdr, pixels).unwrap()
+    }
+
+    #[test]
+    fn test_embed_and_extract() {
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        let mut image = testing_image(ColorType::Rgb, 8);
+        let original = image.samples();
+
+        embed(&mut image, &chunk_type, b"hidden", &LsbOptions::default()).unwrap();
+        let payload = extract(&image, &chunk_type, &LsbOptions::default()).unwrap();
+        assert_eq!(payload, b"hidden");
+
+        let only_low_bits_changed = image
+            .samples()
+            .iter()
+            .zip(original.iter())
+            .all(|(a, b)| a >> 1 == b >> 1);
+        assert!(only_low_bits_changed);
+    }
+
+    #[test]
+    fn test_embed_with_more_bits_and_alpha() {
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        let options = LsbOptions {
+            bits: 3,
+            channels: ChannelMask::from_str("ga").unwrap(),
+            key: None,
+        };
+        let mut image = testing_image(ColorType::Rgba, 16);
+        let message: Vec<u8> = (0..150).collect();
+
+        embed(&mut image, &chunk_type, &message, &options).unwrap();
+        assert_eq!(extract(&image, &chunk_type, &options).unwrap(), message);
+        assert!(extract(&image, &chunk_type, &LsbOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_keyed_embedding_needs_the_key() {
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        let keyed = LsbOptions {
+            key: Some("correct horse".to_string()),
+            ..LsbOptions::default()
+        };
+        let mut image = testing_image(ColorType::Rgb, 8);
+        let original = image.samples();
+
+        embed(&mut image, &chunk_type, b"scattered", &keyed).unwrap();
+        assert_eq!(extract(&image, &chunk_type, &keyed).unwrap(), b"scattered");
+        assert!(extract(&image, &chunk_type, &LsbOptions::default()).is_err());
+
+        let wrong = LsbOptions {
+            key: Some("battery staple".to_string()),
+            ..LsbOptions::default()
+        };
+        assert!(extract(&image, &chunk_type, &wrong).is_err());
+
+        // The payload is not packed into the first samples.
+        let samples = image.samples();
+        let changed: Vec<usize> = (0..original.len())
+            .filter(|&i| samples[i] != original[i])
+            .collect();
+        assert!(changed.iter().any(|&i| i > 200));
+    }
+
+    #[test]
+    fn test_keyed_order_is_pinned() {
+        let mut order: Vec<usize> = (0..10).collect();
+        shuffle(&mut order, &mut keyed_rng("secret"));
+        assert_eq!(order, vec![8, 1, 0, 3, 9, 7, 6, 4, 2, 5]);
+    }
+
+    #[test]
+    fn test_has_unkeyed_message() {
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        let mut image = testing_image(ColorType::Rgb, 8);
+        assert!(!has_unkeyed_message(&image));
+
+        let options = LsbOptions {
+            bits: 2,
+            ..LsbOptions::default()
+        };
+        embed(&mut image, &chunk_type, b"hidden", &options).unwrap();
+        assert!(has_unkeyed_message(&image));
+    }
+
+    #[test]
+    fn test_capacity() {
+        let image = testing_image(ColorType::Grayscale, 8);
+        assert_eq!(capacity(&image, &LsbOptions::default()).unwrap(), 24);
+
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        let mut image = image;
+        let too_long = [0; 25];
+        assert!(embed(&mut image, &chunk_type, &too_long, &LsbOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_extract_checks_magic() {
+        let mut image = testing_image(ColorType::Rgb, 8);
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        embed(&mut image, &chunk_type, b"x", &LsbOptions::default()).unwrap();
+
+        let other = ChunkType::from_str("abCd").unwrap();
+        assert!(extract(&image, &other, &LsbOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_indexed_images_are_rejected() {
+        let ihdr = Ihdr::new(4, 4, ColorType::Indexed, 8).unwrap();
+        let image = Image::new(ihdr, vec![0; 16]).unwrap();
+        assert!(capacity(&image, &LsbOptions::default()).is_err());
+    }
+}
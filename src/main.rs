@@ -1,15 +1,10 @@
 use ::clap::Parser;
-use cli::Cli;
-
-mod chunk;
-mod chunk_type;
-mod cli;
-mod png;
-
-pub type Error = Box<dyn std::error::Error>;
-pub type Result<T> = std::result::Result<T, Error>;
+use pngme::cli::Cli;
 
 fn main() {
     let cli = Cli::parse();
-    cli.run();
+    if let Err(e) = cli.run() {
+        eprintln!("ERROR: {e}");
+        std::process::exit(1);
+    }
 }
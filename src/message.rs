@@ -0,0 +1,391 @@
+use crate::{
+    attachment,
+    chunk_type::ChunkType,
+    compress, crypto, fec,
+    png::Png,
+    recover::{self, Damage},
+    registry,
+    signature::SIGNATURE_CHUNK,
+    split,
+};
+
+/// Marks a payload that starts with the name of its message.
+pub const MAGIC: [u8; 4] = *b"pmN\x01";
+
+/// Prefixes `payload` with `name`, so several messages can share a chunk type.
+pub fn with_name(name: &str, payload: &[u8]) -> crate::Result<Vec<u8>> {
+    if name.is_empty() || name.len() > u8::MAX as usize {
+        return Err("message names must be 1 to 255 bytes long".into());
+    }
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(name.len() as u8);
+    bytes.extend(name.as_bytes());
+    bytes.extend(payload);
+    Ok(bytes)
+}
+
+/// Splits a payload written by `with_name` into its name and the rest.
+/// Payloads without a name are returned unchanged.
+pub fn split_name(data: &[u8]) -> (Option<String>, &[u8]) {
+    if !data.starts_with(&MAGIC) || data.len() < MAGIC.len() + 1 {
+        return (None, data);
+    }
+    let end = MAGIC.len() + 1 + data[MAGIC.len()] as usize;
+    match data
+        .get(MAGIC.len() + 1..end)
+        .and_then(|name| std::str::from_utf8(name).ok())
+    {
+        Some(name) => (Some(name.to_string()), &data[end..]),
+        None => (None, data),
+    }
+}
+
+/// Removes the error correction layer, if any, then the name. Returns the
+/// name, the payload and the number of bytes repaired.
+pub fn open(data: &[u8]) -> crate::Result<(Option<String>, Vec<u8>, usize)> {
+    let (data, corrected) = match fec::is_protected(data) {
+        true => fec::recover(data)?,
+        false => (data.to_vec(), 0),
+    };
+    let (name, payload) = split_name(&data);
+    Ok((name, payload.to_vec(), corrected))
+}
+
+/// Reads a PNG to extract its messages. A damaged byte in a message chunk
+/// also breaks its CRC, so unlike `Png::try_from` this keeps chunks whose CRC
+/// is wrong and leaves the repair to error correction.
+pub fn read_lenient(bytes: &[u8]) -> crate::Result<(Png, Vec<Damage>)> {
+    if let Ok(mut png) = Png::try_from(bytes) {
+        png.adopt_trailing_chunks();
+        return Ok((png, Vec::new()));
+    }
+    if !bytes.starts_with(&Png::STANDARD_HEADER) {
+        return Err("invalid png file".into());
+    }
+    let mut recovery = recover::recover(bytes, true);
+    recovery.png.adopt_trailing_chunks();
+    Ok((recovery.png, recovery.damage))
+}
+
+/// A message hidden in the chunks of a PNG: either a single chunk or all the
+/// parts of a split payload.
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub chunk_type: ChunkType,
+    /// Positions of the chunks holding the message in `Png::chunks`.
+    pub chunks: Vec<usize>,
+    pub name: Option<String>,
+    /// The payload without its name; `None` when parts are missing or the
+    /// damage is beyond repair.
+    pub payload: Option<Vec<u8>>,
+    /// The first missing part numbers, see `split::MAX_LISTED_MISSING`.
+    pub missing: Vec<u32>,
+    pub missing_count: u32,
+    pub duplicates: Vec<u32>,
+    /// Bytes repaired by error correction.
+    pub corrected: usize,
+}
+
+/// Which messages of a chunk type to act on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Selector {
+    First,
+    Name(String),
+    /// Position among the messages of the chunk type, counting from 0.
+    Index(usize),
+    All,
+}
+
+fn is_pngme_data(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+        || fec::is_protected(data)
+        || compress::is_compressed(data)
+        || split::is_part(data)
+        || crypto::is_encrypted(data)
+        || attachment::is_attachment(data)
+}
+
+/// Unregistered private ancillary chunks, such as ruSt but not the APNG
+/// fcTL, and any chunk holding data written by pngme.
+fn is_message_chunk(chunk_type: &ChunkType, data: &[u8]) -> bool {
+    let private_ancillary = !chunk_type.is_critical()
+        && !chunk_type.is_public()
+        && registry::lookup(chunk_type).is_none();
+    chunk_type.to_string() != SIGNATURE_CHUNK && (private_ancillary || is_pngme_data(data))
+}
+
+/// Every message in `png`, in the order their first chunk appears.
+pub fn messages(png: &Png) -> Vec<Message> {
+    let chunks = png.chunks();
+    let mut messages: Vec<Message> = Vec::new();
+    let mut split_ids: Vec<(ChunkType, u64)> = Vec::new();
+
+    for (position, chunk) in chunks.iter().enumerate() {
+        if !is_message_chunk(chunk.chunk_type(), chunk.data()) {
+            continue;
+        }
+        let Ok(part) = split::Part::try_from(chunk.data()) else {
+            let (name, payload, corrected) = match open(chunk.data()) {
+                Ok((name, payload, corrected)) => (name, Some(payload), corrected),
+                Err(_) => (None, None, 0),
+            };
+            messages.push(Message {
+                chunk_type: chunk.chunk_type().clone(),
+                chunks: vec![position],
+                name,
+                payload,
+                missing: Vec::new(),
+                missing_count: 0,
+                duplicates: Vec::new(),
+                corrected,
+            });
+            continue;
+        };
+
+        let key = (chunk.chunk_type().clone(), part.id);
+        if split_ids.contains(&key) {
+            continue;
+        }
+        let positions: Vec<usize> = chunks
+            .iter()
+            .enumerate()
+            .skip(position)
+            .filter(|(_, c)| *c.chunk_type() == key.0)
+            .filter(|(_, c)| split::Part::try_from(c.data()).is_ok_and(|p| p.id == part.id))
+            .map(|(i, _)| i)
+            .collect();
+        let parts: Vec<split::Part> = positions
+            .iter()
+            .filter_map(|&i| split::Part::try_from(chunks[i].data()).ok())
+            .collect();
+
+        // Parts that disagree on their count cannot be put back together.
+        let (missing, missing_count, duplicates, opened) = match split::reassemble(&parts) {
+            Ok(reassembly) => (
+                reassembly.missing,
+                reassembly.missing_count,
+                reassembly.duplicates,
+                reassembly.payload.and_then(|data| open(&data).ok()),
+            ),
+            Err(_) => (Vec::new(), 0, Vec::new(), None),
+        };
+        let (name, payload, corrected) = match opened {
+            Some((name, payload, corrected)) => (name, Some(payload), corrected),
+            None => (None, None, 0),
+        };
+        messages.push(Message {
+            chunk_type: key.0.clone(),
+            chunks: positions,
+            name,
+            payload,
+            missing,
+            missing_count,
+            duplicates,
+            corrected,
+        });
+        split_ids.push(key);
+    }
+    messages
+}
+
+/// The messages of type `chunk_type` picked by `selector`. An unknown name
+/// or index is an error; no message at all is an empty result.
+pub fn select(
+    messages: Vec<Message>,
+    chunk_type: &ChunkType,
+    selector: &Selector,
+) -> crate::Result<Vec<Message>> {
+    let mut of_type = messages
+        .into_iter()
+        .filter(|m| m.chunk_type == *chunk_type)
+        .peekable();
+    if of_type.peek().is_none() {
+        return Ok(Vec::new());
+    }
+
+    match selector {
+        Selector::First => Ok(of_type.take(1).collect()),
+        Selector::All => Ok(of_type.collect()),
+        Selector::Index(index) => of_type
+            .nth(*index)
+            .map(|m| vec![m])
+            .ok_or_else(|| format!("no {chunk_type} message at index {index}").into()),
+        Selector::Name(name) => {
+            let named: Vec<Message> = of_type
+                .filter(|m| m.name.as_deref() == Some(name))
+                .collect();
+            if named.is_empty() {
+                return Err(format!("no {chunk_type} message named {name}").into());
+            }
+            Ok(named)
+        }
+    }
+}
+
+/// Like `select` on the messages of `png`, but when there are none of type
+/// `chunk_type` every chunk of that type is taken as a plain message, such as
+/// a tEXt chunk or a message under a public or critical type.
+pub fn find(png: &Png, chunk_type: &ChunkType, selector: &Selector) -> crate::Result<Vec<Message>> {
+    let found = select(messages(png), chunk_type, selector)?;
+    if !found.is_empty() {
+        return Ok(found);
+    }
+
+    let plain: Vec<Message> = png
+        .chunks()
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| c.chunk_type() == chunk_type)
+        .map(|(position, c)| Message {
+            chunk_type: chunk_type.clone(),
+            chunks: vec![position],
+            name: None,
+            payload: Some(c.data().to_vec()),
+            missing: Vec::new(),
+            missing_count: 0,
+            duplicates: Vec::new(),
+            corrected: 0,
+        })
+        .collect();
+    if !plain.is_empty() && matches!(selector, Selector::Name(_)) {
+        return Err(format!("{chunk_type} chunks are not named messages").into());
+    }
+    select(plain, chunk_type, selector)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: Vec<u8>) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+    }
+
+    fn testing_png() -> Png {
+        let mut chunks = vec![
+            chunk("IHDR", vec![0; 13]),
+            chunk("tEXt", b"Comment\0not a message".to_vec()),
+            chunk("ruSt", b"plain".to_vec()),
+            chunk("ruSt", with_name("first", b"one").unwrap()),
+        ];
+        let named = with_name("big", &[5; 30]).unwrap();
+        for part in split::split(&named, 10).unwrap() {
+            chunks.push(chunk("ruSt", part.as_bytes()));
+        }
+        chunks.push(chunk("abCd", with_name("first", b"other").unwrap()));
+        chunks.push(chunk("IEND", Vec::new()));
+        Png::from_chunks(chunks)
+    }
+
+    #[test]
+    fn test_name_round_trip() {
+        let named = with_name("notes", b"data").unwrap();
+        assert_eq!(
+            split_name(&named),
+            (Some("notes".to_string()), &b"data"[..])
+        );
+        assert_eq!(split_name(b"data"), (None, &b"data"[..]));
+        assert!(with_name("", b"data").is_err());
+    }
+
+    #[test]
+    fn test_damaged_protected_message_is_repaired() {
+        let named = with_name("safe", b"survives a few flipped bytes").unwrap();
+        let mut protected = fec::protect(&named, 8).unwrap();
+        protected[10] ^= 0xff;
+        protected[30] ^= 0x01;
+        let png = Png::from_chunks(vec![chunk("IHDR", vec![0; 13]), chunk("ruSt", protected)]);
+
+        let messages = messages(&png);
+        assert_eq!(messages[0].name.as_deref(), Some("safe"));
+        assert_eq!(
+            messages[0].payload.as_deref(),
+            Some(&b"survives a few flipped bytes"[..])
+        );
+        assert_eq!(messages[0].corrected, 2);
+    }
+
+    #[test]
+    fn test_damaged_file_is_decoded() {
+        use crate::encoder::Encoder;
+        use crate::ihdr::{ColorType, Ihdr};
+
+        let mut png = Encoder::new(Ihdr::new(2, 2, ColorType::Grayscale, 8).unwrap())
+            .encode(&[1, 2, 3, 4])
+            .unwrap();
+        let named = with_name("safe", b"survives a damaged file").unwrap();
+        png.append_chunk(chunk("ruSt", fec::protect(&named, 8).unwrap()));
+        let mut bytes = png.as_bytes();
+        // A data byte of the message chunk, just before IEND.
+        let at = bytes.len() - 12 - 4 - 20;
+        bytes[at] ^= 0xff;
+        assert!(Png::try_from(&bytes[..]).is_err());
+
+        let (png, damage) = read_lenient(&bytes).unwrap();
+        assert_eq!(damage[0].message, "CRC mismatch in ruSt chunk");
+        let messages = messages(&png);
+        assert_eq!(
+            messages[0].payload.as_deref(),
+            Some(&b"survives a damaged file"[..])
+        );
+        assert_eq!(messages[0].corrected, 1);
+    }
+
+    #[test]
+    fn test_messages() {
+        let messages = messages(&testing_png());
+        assert_eq!(messages.len(), 4);
+
+        assert_eq!(messages[0].name, None);
+        assert_eq!(messages[0].payload.as_deref(), Some(&b"plain"[..]));
+        assert_eq!(messages[1].name.as_deref(), Some("first"));
+        assert_eq!(messages[2].name.as_deref(), Some("big"));
+        assert_eq!(messages[2].chunks.len(), 4);
+        assert_eq!(messages[2].payload.as_deref(), Some(&[5; 30][..]));
+        assert_eq!(messages[3].chunk_type.to_string(), "abCd");
+    }
+
+    #[test]
+    fn test_select() {
+        let png = testing_png();
+        let rust = ChunkType::from_str("ruSt").unwrap();
+
+        let first = select(messages(&png), &rust, &Selector::First).unwrap();
+        assert_eq!(first[0].payload.as_deref(), Some(&b"plain"[..]));
+
+        let named = select(messages(&png), &rust, &Selector::Name("first".into())).unwrap();
+        assert_eq!(named.len(), 1);
+        assert_eq!(named[0].payload.as_deref(), Some(&b"one"[..]));
+
+        let indexed = select(messages(&png), &rust, &Selector::Index(2)).unwrap();
+        assert_eq!(indexed[0].name.as_deref(), Some("big"));
+
+        assert_eq!(
+            select(messages(&png), &rust, &Selector::All).unwrap().len(),
+            3
+        );
+        assert!(select(messages(&png), &rust, &Selector::Index(3)).is_err());
+        assert!(select(messages(&png), &rust, &Selector::Name("nope".into())).is_err());
+    }
+
+    #[test]
+    fn test_find_falls_back_to_plain_chunks() {
+        let png = testing_png();
+        let text = ChunkType::from_str("tEXt").unwrap();
+        let found = find(&png, &text, &Selector::First).unwrap();
+        assert_eq!(
+            found[0].payload.as_deref(),
+            Some(&b"Comment\0not a message"[..])
+        );
+        assert_eq!(found[0].chunks, vec![1]);
+        assert!(find(&png, &text, &Selector::Index(1)).is_err());
+        assert!(find(&png, &text, &Selector::Name("first".into())).is_err());
+
+        let rust = ChunkType::from_str("ruSt").unwrap();
+        assert_eq!(find(&png, &rust, &Selector::All).unwrap().len(), 3);
+        let missing = ChunkType::from_str("RuSt").unwrap();
+        assert!(find(&png, &missing, &Selector::First).unwrap().is_empty());
+    }
+}
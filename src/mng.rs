@@ -0,0 +1,231 @@
+use std::str::FromStr;
+
+use crate::{
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    ihdr::{ColorType, Ihdr},
+    png::{Format, Png},
+};
+
+/// The frame size and timing from an MHDR chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mhdr {
+    pub width: u32,
+    pub height: u32,
+    pub ticks_per_second: u32,
+    pub layer_count: u32,
+    pub frame_count: u32,
+    pub play_time: u32,
+    pub simplicity_profile: u32,
+}
+
+impl TryFrom<&[u8]> for Mhdr {
+    type Error = &'static str;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        if value.len() != 28 {
+            return Err("MHDR must be 28 bytes long");
+        }
+        let u32_at = |at: usize| u32::from_be_bytes(value[at..at + 4].try_into().unwrap());
+        Ok(Mhdr {
+            width: u32_at(0),
+            height: u32_at(4),
+            ticks_per_second: u32_at(8),
+            layer_count: u32_at(12),
+            frame_count: u32_at(16),
+            play_time: u32_at(20),
+            simplicity_profile: u32_at(24),
+        })
+    }
+}
+
+/// The contents of a JHDR chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Jhdr {
+    pub width: u32,
+    pub height: u32,
+    /// 8 grey, 10 colour, 12 grey with alpha, 14 colour with alpha.
+    pub color_type: u8,
+    pub sample_depth: u8,
+    pub alpha_sample_depth: u8,
+    /// 0 for a PNG alpha channel in IDAT, 8 for a JPEG one in JDAA.
+    pub alpha_compression: u8,
+}
+
+impl TryFrom<&[u8]> for Jhdr {
+    type Error = &'static str;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        if value.len() != 16 {
+            return Err("JHDR must be 16 bytes long");
+        }
+        Ok(Jhdr {
+            width: u32::from_be_bytes(value[0..4].try_into().unwrap()),
+            height: u32::from_be_bytes(value[4..8].try_into().unwrap()),
+            color_type: value[8],
+            sample_depth: value[9],
+            alpha_sample_depth: value[12],
+            alpha_compression: value[13],
+        })
+    }
+}
+
+impl Jhdr {
+    pub fn has_alpha(&self) -> bool {
+        self.color_type == 12 || self.color_type == 14
+    }
+}
+
+/// An image found inside an MNG or JNG stream.
+pub enum Embedded {
+    Png(Png),
+    /// A JPEG image, or the alpha channel of a JNG stored as a greyscale JPEG.
+    Jpeg(Vec<u8>),
+}
+
+impl Embedded {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Embedded::Png(_) => "png",
+            Embedded::Jpeg(_) => "jpg",
+        }
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        match self {
+            Embedded::Png(png) => png.as_bytes(),
+            Embedded::Jpeg(jpeg) => jpeg.clone(),
+        }
+    }
+}
+
+/// The PNG and JNG images of `container`, in stream order. A JNG gives its
+/// JPEG image, then its alpha channel as a JPEG or a greyscale PNG. Global
+/// chunks such as a shared PLTE are not copied into the images.
+pub fn embedded_images(container: &Png) -> crate::Result<Vec<Embedded>> {
+    if container.format() == Format::Png {
+        return Ok(vec![Embedded::Png(Png::from_chunks(container.chunks()))]);
+    }
+
+    let mut images = Vec::new();
+    let mut current: Option<Vec<Chunk>> = None;
+    for chunk in container.chunks() {
+        let chunk_type = chunk.chunk_type().to_string();
+        if chunk_type == "IHDR" || chunk_type == "JHDR" {
+            current = Some(Vec::new());
+        }
+        let Some(chunks) = current.as_mut() else {
+            continue;
+        };
+        chunks.push(chunk);
+        if chunk_type == "IEND" {
+            let chunks = current.take().unwrap();
+            match chunks[0].chunk_type().to_string() == "JHDR" {
+                true => images.extend(jng_images(&chunks)?),
+                false => images.push(Embedded::Png(Png::from_chunks(chunks))),
+            }
+        }
+    }
+    Ok(images)
+}
+
+fn jng_images(chunks: &[Chunk]) -> crate::Result<Vec<Embedded>> {
+    let jhdr = Jhdr::try_from(chunks[0].data())?;
+    let data_of = |chunk_type: &str| -> Vec<u8> {
+        chunks
+            .iter()
+            .filter(|c| c.chunk_type().to_string() == chunk_type)
+            .flat_map(|c| c.data().iter().copied())
+            .collect()
+    };
+
+    let mut images = vec![Embedded::Jpeg(data_of("JDAT"))];
+    if !jhdr.has_alpha() {
+        return Ok(images);
+    }
+    match jhdr.alpha_compression {
+        8 => images.push(Embedded::Jpeg(data_of("JDAA"))),
+        _ => {
+            let ihdr = Ihdr::new(
+                jhdr.width,
+                jhdr.height,
+                ColorType::Grayscale,
+                jhdr.alpha_sample_depth,
+            )?;
+            let mut alpha = vec![ihdr.to_chunk()];
+            alpha.extend(
+                chunks
+                    .iter()
+                    .filter(|c| c.chunk_type().to_string() == "IDAT")
+                    .cloned(),
+            );
+            alpha.push(Chunk::new(ChunkType::from_str("IEND")?, Vec::new()));
+            images.push(Embedded::Png(Png::from_chunks(alpha)));
+        }
+    }
+    Ok(images)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::Encoder;
+
+    fn chunk(chunk_type: &str, data: Vec<u8>) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+    }
+
+    fn stream(format: Format, chunks: &[Chunk]) -> Vec<u8> {
+        format
+            .signature()
+            .into_iter()
+            .chain(chunks.iter().flat_map(|c| c.as_bytes()))
+            .collect()
+    }
+
+    #[test]
+    fn test_read_mng() {
+        let still = Encoder::new(Ihdr::new(2, 2, ColorType::Grayscale, 8).unwrap())
+            .encode(&[9; 4])
+            .unwrap();
+        let mut mhdr = vec![0; 28];
+        mhdr[3] = 2;
+        mhdr[7] = 2;
+        let mut chunks = vec![chunk("MHDR", mhdr)];
+        chunks.extend(still.chunks());
+        chunks.extend(still.chunks());
+        chunks.push(chunk("MEND", Vec::new()));
+        let bytes = stream(Format::Mng, &chunks);
+
+        assert!(Png::try_from(&bytes[..]).is_err());
+        let mng = Png::read_container(&bytes).unwrap();
+        assert_eq!(mng.format(), Format::Mng);
+        assert_eq!(mng.chunks().len(), 2 * still.chunks().len() + 2);
+        assert_eq!(mng.as_bytes(), bytes);
+        assert_eq!(Mhdr::try_from(mng.chunks()[0].data()).unwrap().width, 2);
+
+        let images = embedded_images(&mng).unwrap();
+        assert_eq!(images.len(), 2);
+        assert_eq!(images[1].as_bytes(), still.as_bytes());
+    }
+
+    #[test]
+    fn test_read_jng() {
+        let jhdr = vec![0, 0, 0, 4, 0, 0, 0, 3, 12, 8, 8, 0, 8, 8, 0, 0];
+        let chunks = [
+            chunk("JHDR", jhdr),
+            chunk("JDAT", vec![0xff, 0xd8]),
+            chunk("JDAT", vec![0xff, 0xd9]),
+            chunk("JDAA", vec![1, 2, 3]),
+            chunk("IEND", Vec::new()),
+        ];
+        let jng = Png::read_container(&stream(Format::Jng, &chunks)).unwrap();
+        assert_eq!(jng.format(), Format::Jng);
+
+        let images = embedded_images(&jng).unwrap();
+        assert_eq!(images.len(), 2);
+        assert_eq!(images[0].as_bytes(), vec![0xff, 0xd8, 0xff, 0xd9]);
+        assert_eq!(images[1].as_bytes(), vec![1, 2, 3]);
+        assert_eq!(images[1].extension(), "jpg");
+    }
+}
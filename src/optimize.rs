@@ -0,0 +1,374 @@
+use std::collections::HashMap;
+
+use crate::{
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    decoder::Image,
+    encoder::{replace_image, Dropped},
+    filter::{FilterStrategy, FilterType},
+    ihdr::{ColorType, Ihdr},
+    lsb,
+    png::Png,
+};
+
+#[derive(Debug, Clone)]
+pub struct OptimizeOptions {
+    /// Ancillary chunk types to remove from the output.
+    pub strip: Vec<ChunkType>,
+    /// Whether to try smaller colour types and bit depths.
+    pub reduce: bool,
+    /// Zlib compression levels to try.
+    pub levels: Vec<u32>,
+    /// Keep unknown unsafe-to-copy chunks, against the spec's advice.
+    pub keep_unsafe: bool,
+}
+
+impl Default for OptimizeOptions {
+    fn default() -> Self {
+        OptimizeOptions {
+            strip: Vec::new(),
+            reduce: true,
+            levels: vec![6, 9],
+            keep_unsafe: false,
+        }
+    }
+}
+
+/// The outcome of `optimize`. `settings` is `None` when no re-encoding beat
+/// the original image data and only stripping was applied.
+pub struct Optimized {
+    pub png: Png,
+    pub original_size: usize,
+    pub settings: Option<(Ihdr, FilterStrategy, u32)>,
+    pub stripped: Vec<Chunk>,
+    pub dropped: Vec<Dropped>,
+}
+
+impl Optimized {
+    pub fn optimized_size(&self) -> usize {
+        self.png.as_bytes().len()
+    }
+}
+
+const STRATEGIES: [FilterStrategy; 6] = [
+    FilterStrategy::Fixed(FilterType::None),
+    FilterStrategy::Fixed(FilterType::Sub),
+    FilterStrategy::Fixed(FilterType::Up),
+    FilterStrategy::Fixed(FilterType::Average),
+    FilterStrategy::Fixed(FilterType::Paeth),
+    FilterStrategy::Adaptive,
+];
+
+/// Whether `png` is an APNG. Its fdAT frames are stored in the format of
+/// IHDR and are not re-encoded, so its colour type must not change.
+pub fn is_animated(png: &Png) -> bool {
+    png.chunk_by_type("acTL").is_some()
+}
+
+/// Re-encodes the image data of `png` with every combination of colour
+/// reduction, filter strategy and compression level, keeping the smallest.
+/// Chunks outside the image data, such as hidden messages, are left in place.
+/// Colour reduction is skipped for animated PNGs and for images holding a
+/// message hidden in the pixels without a key. It still destroys messages
+/// hidden with a key, which cannot be detected; pass `reduce: false` to keep
+/// them.
+pub fn optimize(png: &Png, options: &OptimizeOptions) -> crate::Result<Optimized> {
+    let original_size = png.as_bytes().len();
+
+    let mut kept = Vec::new();
+    let mut stripped = Vec::new();
+    for chunk in png.chunks() {
+        if options.strip.contains(chunk.chunk_type()) {
+            if chunk.chunk_type().is_critical() {
+                return Err(format!("cannot strip critical chunk {}", chunk.chunk_type()).into());
+            }
+            stripped.push(chunk);
+        } else {
+            kept.push(chunk);
+        }
+    }
+    let mut source = Png::from_chunks(kept);
+    source.set_trailing_data(png.trailing_data().to_vec());
+
+    let image = Image::try_from(png)?;
+    let mut candidates = vec![image.clone()];
+    if options.reduce && !is_animated(png) && !lsb::has_unkeyed_message(&image) {
+        candidates.extend(reductions(&image)?);
+    }
+
+    let mut best: Option<Candidate> = None;
+    for candidate in candidates.iter() {
+        for strategy in STRATEGIES {
+            for &level in options.levels.iter() {
+                let encoded = candidate
+                    .encoder()
+                    .with_filter(strategy)
+                    .with_compression(level)
+                    .with_idat_size(u32::MAX as usize >> 1)
+                    .encode(candidate.pixels())?;
+                let (result, dropped) = replace_image(&source, &encoded, options.keep_unsafe)?;
+                let size = result.as_bytes().len();
+                if best.as_ref().is_none_or(|b| size < b.0) {
+                    let settings = (candidate.ihdr().clone(), strategy, level);
+                    best = Some((size, result, settings, dropped));
+                }
+            }
+        }
+    }
+
+    match best {
+        Some((size, result, settings, dropped)) if size < source.as_bytes().len() => {
+            Ok(Optimized {
+                png: result,
+                original_size,
+                settings: Some(settings),
+                stripped,
+                dropped,
+            })
+        }
+        _ => Ok(Optimized {
+            png: source,
+            original_size,
+            settings: None,
+            stripped,
+            dropped: Vec::new(),
+        }),
+    }
+}
+
+type Candidate = (usize, Png, (Ihdr, FilterStrategy, u32), Vec<Dropped>);
+
+/// Lossless re-encodings of `image` in smaller colour types or bit depths:
+/// 16-bit samples that fit in 8 bits, opaque alpha removed, grey RGB turned
+/// into grayscale, and images with at most 256 colours turned into palettes.
+pub fn reductions(image: &Image) -> crate::Result<Vec<Image>> {
+    let (mut pixels, mut depth) = image.to_rgba()?;
+
+    if depth == 16 && pixels.iter().flatten().all(|&v| v % 257 == 0) {
+        for pixel in pixels.iter_mut() {
+            for v in pixel.iter_mut() {
+                *v /= 257;
+            }
+        }
+        depth = 8;
+    }
+
+    let max = if depth == 16 { u16::MAX } else { 255 };
+    let opaque = pixels.iter().all(|p| p[3] == max);
+    let gray = pixels.iter().all(|p| p[0] == p[1] && p[1] == p[2]);
+    let (width, height) = (image.ihdr().width(), image.ihdr().height());
+
+    let color_type = match (gray, opaque) {
+        (true, true) => ColorType::Grayscale,
+        (true, false) => ColorType::GrayscaleAlpha,
+        (false, true) => ColorType::Rgb,
+        (false, false) => ColorType::Rgba,
+    };
+    let samples: Vec<u16> = pixels
+        .iter()
+        .flat_map(|p| match color_type {
+            ColorType::Grayscale => p[..1].to_vec(),
+            ColorType::GrayscaleAlpha => vec![p[0], p[3]],
+            ColorType::Rgb => p[..3].to_vec(),
+            _ => p.to_vec(),
+        })
+        .collect();
+
+    let ihdr = Ihdr::new(width, height, color_type, depth)?;
+    let mut direct = Image::new(ihdr.clone(), vec![0; ihdr.image_bytes()])?;
+    direct.set_samples(&samples)?;
+    let mut reduced = vec![direct];
+
+    if depth == 8 {
+        if let Some(indexed) = to_palette(&pixels, width, height)? {
+            reduced.push(indexed);
+        }
+    }
+
+    reduced.retain(|candidate| {
+        candidate.ihdr().color_type() != image.ihdr().color_type()
+            || candidate.ihdr().bit_depth() != image.ihdr().bit_depth()
+    });
+    Ok(reduced)
+}
+
+fn to_palette(pixels: &[[u16; 4]], width: u32, height: u32) -> crate::Result<Option<Image>> {
+    let mut colors: Vec<[u16; 4]> = Vec::new();
+    let mut indices: HashMap<[u16; 4], u16> = HashMap::new();
+    for pixel in pixels.iter() {
+        if !indices.contains_key(pixel) {
+            if colors.len() == 256 {
+                return Ok(None);
+            }
+            indices.insert(*pixel, 0);
+            colors.push(*pixel);
+        }
+    }
+
+    // Translucent entries go first so the tRNS chunk can stop at the last one.
+    colors.sort_by_key(|c| c[3] == 255);
+    for (i, color) in colors.iter().enumerate() {
+        indices.insert(*color, i as u16);
+    }
+
+    let bit_depth = match colors.len() {
+        0..=2 => 1,
+        3..=4 => 2,
+        5..=16 => 4,
+        _ => 8,
+    };
+    let ihdr = Ihdr::new(width, height, ColorType::Indexed, bit_depth)?;
+    let samples: Vec<u16> = pixels.iter().map(|p| indices[p]).collect();
+    let palette: Vec<u8> = colors
+        .iter()
+        .flat_map(|c| [c[0] as u8, c[1] as u8, c[2] as u8])
+        .collect();
+    let transparency: Vec<u8> = colors
+        .iter()
+        .take_while(|c| c[3] != 255)
+        .map(|c| c[3] as u8)
+        .collect();
+
+    let mut image = Image::new(ihdr.clone(), vec![0; ihdr.image_bytes()])?.with_palette(palette);
+    if !transparency.is_empty() {
+        image = image.with_transparency(transparency);
+    }
+    image.set_samples(&samples)?;
+    Ok(Some(image))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apng;
+    use crate::encoder::Encoder;
+    use crate::message;
+    use std::str::FromStr;
+
+    fn opaque_rgba_png() -> Png {
+        let ihdr = Ihdr::new(16, 16, ColorType::Rgba, 8).unwrap();
+        let pixels: Vec<u8> = (0..256u32)
+            .flat_map(|i| [(i % 3 * 100) as u8, 40, 200, 255])
+            .collect();
+        Encoder::new(ihdr)
+            .with_compression(0)
+            .encode(&pixels)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_reductions_drop_opaque_alpha_and_use_palette() {
+        let image = Image::try_from(&opaque_rgba_png()).unwrap();
+        let reduced = reductions(&image).unwrap();
+
+        let types: Vec<ColorType> = reduced.iter().map(|i| i.ihdr().color_type()).collect();
+        assert_eq!(types, vec![ColorType::Rgb, ColorType::Indexed]);
+        assert_eq!(reduced[1].ihdr().bit_depth(), 2);
+        for candidate in reduced {
+            assert_eq!(candidate.to_rgba().unwrap(), image.to_rgba().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_optimize_shrinks_and_preserves_pixels_and_messages() {
+        let mut png = opaque_rgba_png();
+        png.append_chunk(
+            Chunk::chunk_from_strings("ruSt".to_string(), "hidden".to_string()).unwrap(),
+        );
+
+        let optimized = optimize(&png, &OptimizeOptions::default()).unwrap();
+        assert!(optimized.optimized_size() < optimized.original_size);
+
+        let before = Image::try_from(&png).unwrap().to_rgba().unwrap();
+        let after = Image::try_from(&optimized.png).unwrap().to_rgba().unwrap();
+        assert_eq!(before, after);
+        assert_eq!(
+            optimized
+                .png
+                .chunk_by_type("ruSt")
+                .unwrap()
+                .data_as_string()
+                .unwrap(),
+            "hidden"
+        );
+    }
+
+    #[test]
+    fn test_optimize_keeps_unsafe_to_copy_messages() {
+        let mut png = opaque_rgba_png();
+        let named = message::with_name("notes", b"hidden").unwrap();
+        png.append_chunk(Chunk::new(ChunkType::from_str("ruST").unwrap(), named));
+
+        let optimized = optimize(&png, &OptimizeOptions::default()).unwrap();
+        assert!(optimized.settings.is_some());
+        assert!(optimized.dropped.is_empty());
+        let messages = message::messages(&optimized.png);
+        assert_eq!(messages[0].name.as_deref(), Some("notes"));
+        assert_eq!(messages[0].payload.as_deref(), Some(&b"hidden"[..]));
+    }
+
+    #[test]
+    fn test_optimize_keeps_animation_format() {
+        let frame = opaque_rgba_png();
+        let animation =
+            apng::build(&[Png::from_chunks(frame.chunks()), frame], (1, 10), 0).unwrap();
+        assert!(is_animated(&animation));
+
+        let optimized = optimize(&animation, &OptimizeOptions::default()).unwrap();
+        let ihdr = optimized.png.ihdr().unwrap();
+        assert_eq!(ihdr.color_type(), ColorType::Rgba);
+        assert!(apng::validate(&optimized.png).unwrap().is_empty());
+        assert_eq!(
+            optimized.png.chunks_by_type("fdAT").len(),
+            animation.chunks_by_type("fdAT").len()
+        );
+    }
+
+    #[test]
+    fn test_optimize_keeps_lsb_messages() {
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        let options = lsb::LsbOptions::default();
+        // Few colours, so a palette would be the smallest encoding.
+        let ihdr = Ihdr::new(64, 64, ColorType::Rgba, 8).unwrap();
+        let pixels: Vec<u8> = (0..64 * 64u32)
+            .flat_map(|i| [(i % 2 * 100) as u8, 40, 200, 255])
+            .collect();
+        let png = Encoder::new(ihdr).encode(&pixels).unwrap();
+        let mut image = Image::try_from(&png).unwrap();
+        lsb::embed(&mut image, &chunk_type, b"in the pixels", &options).unwrap();
+        let encoded = image.encoder().encode(image.pixels()).unwrap();
+        let (png, _) = replace_image(&png, &encoded, false).unwrap();
+
+        let optimized = optimize(&png, &OptimizeOptions::default()).unwrap();
+        let image = Image::try_from(&optimized.png).unwrap();
+        assert_eq!(
+            lsb::extract(&image, &chunk_type, &options).unwrap(),
+            b"in the pixels"
+        );
+    }
+
+    #[test]
+    fn test_optimize_strips_chunks() {
+        let mut png = opaque_rgba_png();
+        png.append_chunk(
+            Chunk::chunk_from_strings("tEXt".to_string(), "Comment\0hi".to_string()).unwrap(),
+        );
+        let options = OptimizeOptions {
+            strip: vec![ChunkType::from_str("tEXt").unwrap()],
+            ..OptimizeOptions::default()
+        };
+
+        let optimized = optimize(&png, &options).unwrap();
+        assert_eq!(optimized.stripped.len(), 1);
+        assert!(optimized.png.chunk_by_type("tEXt").is_none());
+    }
+
+    #[test]
+    fn test_optimize_refuses_to_strip_critical_chunks() {
+        let options = OptimizeOptions {
+            strip: vec![ChunkType::from_str("IDAT").unwrap()],
+            ..OptimizeOptions::default()
+        };
+        assert!(optimize(&opaque_rgba_png(), &options).is_err());
+    }
+}
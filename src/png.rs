@@ -1,21 +1,77 @@
-use crate::{chunk::Chunk, chunk_type::ChunkType}; // chunk_type::ChunkType, Error};
+use crate::{chunk::Chunk, chunk_type::ChunkType, encoder::split_idat, ihdr::Ihdr};
 use std::{
     fmt::Display,
-    fs::File,
     io::{BufReader, Read},
     path::Path,
     str::FromStr,
 };
 
+/// Streams that share the PNG chunk layout and differ in their signature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Png,
+    /// Multiple-image Network Graphics, ending with MEND.
+    Mng,
+    /// JPEG Network Graphics, a JPEG image with an optional PNG alpha channel.
+    Jng,
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Format::Png => "PNG",
+            Format::Mng => "MNG",
+            Format::Jng => "JNG",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl Format {
+    pub const ALL: [Format; 3] = [Format::Png, Format::Mng, Format::Jng];
+
+    pub fn signature(&self) -> [u8; 8] {
+        match self {
+            Format::Png => Png::STANDARD_HEADER,
+            Format::Mng => [138, 77, 78, 71, 13, 10, 26, 10],
+            Format::Jng => [139, 74, 78, 71, 13, 10, 26, 10],
+        }
+    }
+
+    /// The chunk that ends the stream. MNG streams contain whole PNG
+    /// images, so their own IEND chunks do not end it.
+    pub fn end_chunk(&self) -> &'static str {
+        match self {
+            Format::Mng => "MEND",
+            Format::Png | Format::Jng => "IEND",
+        }
+    }
+
+    pub fn from_signature(signature: &[u8]) -> Option<Format> {
+        Format::ALL
+            .into_iter()
+            .find(|format| signature == format.signature())
+    }
+}
+
 pub struct Png {
-    signature: [u8; 8],
+    format: Format,
     chunks: Vec<Chunk>,
+    /// Whatever follows IEND, such as a ZIP archive appended to the image.
+    trailing: Vec<u8>,
 }
 
 impl TryFrom<&[u8]> for Png {
     type Error = ();
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        Png::parse(value, &[Format::Png])
+    }
+}
+
+impl Png {
+    /// Reads a stream in any of `formats`, stopping at its end chunk.
+    fn parse(value: &[u8], formats: &[Format]) -> Result<Png, ()> {
         let mut chunk_vector: Vec<Chunk> = Vec::new();
 
         let mut reader = BufReader::new(value);
@@ -24,37 +80,50 @@ impl TryFrom<&[u8]> for Png {
         let mut chunk_type_buf: [u8; 4] = [0; 4];
         let mut crc_buf: [u8; 4] = [0; 4];
 
-        reader.read_exact(&mut signature_buf);
-
-        while let Ok(_chunk_len) = reader.read_exact(&mut length_buf) {
-            if let Ok(e) = reader.read_exact(&mut chunk_type_buf) {
-                let ulen: u8 = u32::from_be_bytes(length_buf) as u8;
-                let mut data_buf: Vec<_> = (0..ulen).collect();
-                if let Ok(e) = reader.read_exact(&mut data_buf) {
-                    if let Ok(e) = reader.read_exact(&mut crc_buf) {
-                        let chunk_data: Vec<u8> = length_buf
-                            .iter()
-                            .chain(chunk_type_buf.iter())
-                            .chain(data_buf.iter())
-                            .chain(crc_buf.iter())
-                            .copied()
-                            .collect();
-                        let chunk = Chunk::try_from(chunk_data.as_ref()).unwrap();
-                        chunk_vector.push(chunk);
-                    } else {
-                        return Err(e);
-                    }
-                } else {
-                    return Err(e);
-                }
-            } else {
+        reader.read_exact(&mut signature_buf).map_err(|_| ())?;
+        let format = match Format::from_signature(&signature_buf) {
+            Some(format) if formats.contains(&format) => format,
+            _ => return Err(()),
+        };
+
+        let mut at = signature_buf.len();
+        while reader.read_exact(&mut length_buf).is_ok() {
+            reader.read_exact(&mut chunk_type_buf).map_err(|_| ())?;
+            let len = u32::from_be_bytes(length_buf) as usize;
+            // Check the length before allocating, so a damaged or hostile
+            // length cannot make us reserve gigabytes for a short file.
+            if len > Chunk::MAX_LENGTH as usize || at + 12 + len > value.len() {
                 return Err(());
             }
+            at += 12 + len;
+            let mut data_buf: Vec<u8> = vec![0; len];
+            reader.read_exact(&mut data_buf).map_err(|_| ())?;
+            reader.read_exact(&mut crc_buf).map_err(|_| ())?;
+
+            let chunk_data: Vec<u8> = length_buf
+                .iter()
+                .chain(chunk_type_buf.iter())
+                .chain(data_buf.iter())
+                .chain(crc_buf.iter())
+                .copied()
+                .collect();
+            let chunk = Chunk::try_from(chunk_data.as_ref()).map_err(|_| ())?;
+            let is_end = chunk.chunk_type().to_string() == format.end_chunk();
+            chunk_vector.push(chunk);
+            // Nothing after IEND is parsed, so appended archives or scripts
+            // are kept as they are instead of being misread as chunks.
+            if is_end {
+                break;
+            }
         }
 
+        let mut trailing = Vec::new();
+        reader.read_to_end(&mut trailing).map_err(|_| ())?;
+
         Ok(Png {
-            signature: Png::STANDARD_HEADER,
+            format,
             chunks: chunk_vector,
+            trailing,
         })
     }
 }
@@ -70,71 +139,205 @@ impl Display for Png {
 }
 
 impl Png {
-    const STANDARD_HEADER: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+    pub const STANDARD_HEADER: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
 
     fn signature(&self) -> [u8; 8] {
-        Self::STANDARD_HEADER
+        self.format.signature()
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Like `try_from`, but also accepts MNG and JNG streams.
+    pub fn read_container(value: &[u8]) -> Result<Png, &'static str> {
+        Png::parse(value, &Format::ALL).map_err(|_| "not a PNG, MNG or JNG stream")
     }
 
     pub fn chunks(&self) -> Vec<Chunk> {
         self.chunks.clone()
     }
 
-    fn from_chunks(chunks: Vec<Chunk>) -> Png {
+    pub fn from_chunks(chunks: Vec<Chunk>) -> Png {
         Png {
-            signature: Png::STANDARD_HEADER,
-            chunks: chunks,
+            format: Format::Png,
+            chunks,
+            trailing: Vec::new(),
         }
     }
 
-    pub fn append_chunk(&mut self, chunk: Chunk) -> () {
-        self.chunks.push(chunk);
+    /// Adds `chunk` just before IEND, or at the end when there is none.
+    pub fn append_chunk(&mut self, chunk: Chunk) {
+        match self
+            .chunks
+            .iter()
+            .position(|c| c.chunk_type().to_string() == "IEND")
+        {
+            Some(iend) => self.chunks.insert(iend, chunk),
+            None => self.chunks.push(chunk),
+        }
     }
 
+    /// Inserts `chunk` at `index`, shifting the chunks after it.
+    pub fn insert_chunk(&mut self, index: usize, chunk: Chunk) -> Result<(), &'static str> {
+        if index > self.chunks.len() {
+            return Err("chunk index out of range");
+        }
+        self.chunks.insert(index, chunk);
+        Ok(())
+    }
+
+    /// Puts `chunk` in place of the chunk at `index` and returns the old one.
+    pub fn replace_chunk(&mut self, index: usize, chunk: Chunk) -> Result<Chunk, &'static str> {
+        match self.chunks.get_mut(index) {
+            Some(old) => Ok(std::mem::replace(old, chunk)),
+            None => Err("chunk index out of range"),
+        }
+    }
+
+    /// Removes the first chunk of the given type.
     pub fn remove_chunk(&mut self, chunk_type_str: &str) -> Result<Chunk, &'static str> {
-        let chunk_type = ChunkType::from_str(chunk_type_str).unwrap();
-        if let Some(i) = self
+        let chunk_type = ChunkType::from_str(chunk_type_str)?;
+        match self
             .chunks
             .iter()
             .position(|c| *c.chunk_type() == chunk_type)
         {
-            return Ok(self.chunks.remove(i));
-        } else {
-            return Err("NOT FOUND");
+            Some(i) => Ok(self.chunks.remove(i)),
+            None => Err("NOT FOUND"),
+        }
+    }
+
+    /// Removes every chunk for which `f` returns true and returns them.
+    pub fn remove_where<F: FnMut(&Chunk) -> bool>(&mut self, mut f: F) -> Vec<Chunk> {
+        let (removed, kept) = std::mem::take(&mut self.chunks)
+            .into_iter()
+            .partition(|c| f(c));
+        self.chunks = kept;
+        removed
+    }
+
+    /// Keeps only the chunks for which `f` returns true.
+    pub fn retain<F: FnMut(&Chunk) -> bool>(&mut self, f: F) {
+        self.chunks.retain(f);
+    }
+
+    /// The chunks for which `f` returns true, in file order.
+    pub fn chunks_where<F: FnMut(&Chunk) -> bool>(&self, mut f: F) -> Vec<Chunk> {
+        self.chunks.iter().filter(|c| f(c)).cloned().collect()
+    }
+
+    /// Every chunk of the given type; none when the type is invalid.
+    pub fn chunks_by_type(&self, chunk_type_str: &str) -> Vec<Chunk> {
+        match ChunkType::from_str(chunk_type_str) {
+            Ok(chunk_type) => self.chunks_where(|c| *c.chunk_type() == chunk_type),
+            Err(_) => Vec::new(),
         }
     }
 
+    /// The first chunk of the given type; none when the type is invalid.
     pub fn chunk_by_type(&self, chunk_type_str: &str) -> Option<Chunk> {
-        let chunk_type = ChunkType::from_str(chunk_type_str).unwrap();
+        let chunk_type = ChunkType::from_str(chunk_type_str).ok()?;
 
-        if let Some(found) = self
-            .chunks()
+        self.chunks
             .iter()
             .find(|&c| *c.chunk_type() == chunk_type)
-        {
-            let copy = found.clone();
-            return Some(copy);
-        } else {
-            return None;
+            .cloned()
+    }
+
+    pub fn ihdr(&self) -> crate::Result<Ihdr> {
+        match self.chunk_by_type("IHDR") {
+            Some(chunk) => Ok(Ihdr::try_from(chunk.data())?),
+            None => Err("missing IHDR chunk".into()),
         }
     }
 
-    pub fn as_bytes(&self) -> Vec<u8> {
-        self.as_bytes()
-    }
-
-    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, std::io::Error> {
-        match File::open(path) {
-            Ok(file) => {
-                let mut buf_reader = BufReader::new(file);
-                let mut contents = String::new();
-                buf_reader.read_to_string(&mut contents)?;
-                let contents_as_u8: &[u8] = contents.as_bytes();
-                let png = Png::try_from(contents_as_u8).unwrap();
-                return Ok(png);
+    /// The concatenated contents of every IDAT chunk, i.e. the zlib stream.
+    pub fn idat_data(&self) -> Vec<u8> {
+        self.chunks
+            .iter()
+            .filter(|c| c.chunk_type().to_string() == "IDAT")
+            .flat_map(|c| c.data().iter().copied())
+            .collect()
+    }
+
+    /// Redistributes the IDAT stream over chunks of at most `max_len` bytes,
+    /// placed where the first IDAT chunk was. The stream itself is not touched.
+    pub fn rechunk_idat(&mut self, max_len: usize) -> crate::Result<()> {
+        if max_len == 0 || max_len > i32::MAX as usize {
+            return Err("IDAT length must be between 1 and 2^31 - 1".into());
+        }
+
+        let first = self
+            .chunks
+            .iter()
+            .position(|c| c.chunk_type().to_string() == "IDAT")
+            .ok_or("missing IDAT chunk")?;
+        let idats = split_idat(&self.idat_data(), max_len);
+
+        self.chunks.retain(|c| c.chunk_type().to_string() != "IDAT");
+        self.chunks.splice(first..first, idats);
+        Ok(())
+    }
+
+    /// The bytes found after IEND, empty for most files.
+    pub fn trailing_data(&self) -> &[u8] {
+        &self.trailing
+    }
+
+    pub fn set_trailing_data(&mut self, trailing: Vec<u8>) {
+        self.trailing = trailing;
+    }
+
+    /// Removes and returns the bytes after IEND.
+    pub fn strip_trailing_data(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.trailing)
+    }
+
+    /// Moves chunks found after IEND to just before it. Older versions of
+    /// pngme appended messages after IEND, where they now parse as trailing
+    /// data. Nothing moves unless the trailing data is entirely made of
+    /// intact chunks. Returns the number of chunks moved.
+    pub fn adopt_trailing_chunks(&mut self) -> usize {
+        if self.trailing.is_empty() {
+            return 0;
+        }
+        let bytes: Vec<u8> = self
+            .signature()
+            .into_iter()
+            .chain(self.trailing.iter().copied())
+            .collect();
+        match Png::parse(&bytes, &[self.format]) {
+            Ok(late) if late.trailing.is_empty() && !late.chunks.is_empty() => {
+                let moved = late.chunks.len();
+                for chunk in late.chunks {
+                    self.append_chunk(chunk);
+                }
+                self.trailing.clear();
+                moved
             }
-            Err(e) => return Err(e),
-        };
+            _ => 0,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        self.signature()
+            .iter()
+            .copied()
+            .chain(self.chunks.iter().flat_map(|chunk| chunk.as_bytes()))
+            .chain(self.trailing.iter().copied())
+            .collect()
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> crate::Result<Self> {
+        let contents = std::fs::read(path)?;
+        Png::try_from(&contents[..]).map_err(|_| "invalid png file".into())
+    }
+
+    /// Like `from_file`, but also accepts MNG and JNG files.
+    pub fn container_from_file<P: AsRef<Path>>(path: P) -> crate::Result<Self> {
+        let contents = std::fs::read(path)?;
+        Ok(Png::read_container(&contents[..])?)
     }
 }
 
@@ -147,22 +350,14 @@ mod tests {
     //use std::str::FromStr;
 
     fn testing_chunks() -> Vec<Chunk> {
-        let mut chunks = Vec::new();
-
-        chunks.push(
+        vec![
             Chunk::chunk_from_strings("FrSt".to_string(), "I am the first chunk".to_string())
                 .unwrap(),
-        );
-        chunks.push(
             Chunk::chunk_from_strings("miDl".to_string(), "I am another chunk".to_string())
                 .unwrap(),
-        );
-        chunks.push(
             Chunk::chunk_from_strings("LASt".to_string(), "I am the last chunk".to_string())
                 .unwrap(),
-        );
-
-        chunks
+        ]
     }
 
     fn testing_png() -> Png {
@@ -235,6 +430,68 @@ mod tests {
         assert!(chunk.is_none());
     }
 
+    #[test]
+    fn test_rejects_oversized_length() {
+        let mut bytes = Png::STANDARD_HEADER.to_vec();
+        bytes.extend([0xff, 0xff, 0xff, 0xf0]);
+        bytes.extend(b"IDAT");
+        assert!(Png::try_from(&bytes[..]).is_err());
+        bytes[8] = 0x7f;
+        assert!(Png::try_from(&bytes[..]).is_err());
+    }
+
+    #[test]
+    fn test_remove_where() {
+        let mut png = testing_png();
+        let removed = png.remove_where(|c| !c.chunk_type().is_critical());
+        assert_eq!(removed.len(), 1);
+        assert_eq!(&removed[0].chunk_type().to_string(), "miDl");
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, vec!["FrSt", "LASt"]);
+    }
+
+    #[test]
+    fn test_query_invalid_type() {
+        let mut png = testing_png();
+        assert!(png.chunk_by_type("1234").is_none());
+        assert!(png.chunks_by_type("1234").is_empty());
+        assert!(png.remove_chunk("1234").is_err());
+    }
+
+    #[test]
+    fn test_chunks_by_type() {
+        let mut png = testing_png();
+        png.append_chunk(
+            Chunk::chunk_from_strings("miDl".to_string(), "Another middle chunk".to_string())
+                .unwrap(),
+        );
+        let chunks = png.chunks_by_type("miDl");
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(&chunks[1].data_as_string().unwrap(), "Another middle chunk");
+        assert_eq!(png.chunks_where(|c| c.length() == 20).len(), 2);
+
+        png.retain(|c| c.chunk_type().to_string() != "miDl");
+        assert_eq!(png.chunks().len(), 2);
+    }
+
+    #[test]
+    fn test_insert_and_replace_chunk() {
+        let mut png = testing_png();
+        let chunk = Chunk::chunk_from_strings("TeSt".to_string(), "Message".to_string()).unwrap();
+        png.insert_chunk(1, chunk.clone()).unwrap();
+        assert_eq!(&png.chunks()[1].chunk_type().to_string(), "TeSt");
+        assert!(png.insert_chunk(5, chunk.clone()).is_err());
+
+        let old = png.replace_chunk(0, chunk.clone()).unwrap();
+        assert_eq!(&old.chunk_type().to_string(), "FrSt");
+        assert_eq!(png.chunks_by_type("TeSt").len(), 2);
+        assert!(png.replace_chunk(4, chunk).is_err());
+    }
+
     #[test]
     fn test_chunk_by_type() {
         let png = testing_png();
@@ -243,6 +500,86 @@ mod tests {
         assert_eq!(&chunk.data_as_string().unwrap(), "I am the first chunk");
     }
 
+    #[test]
+    fn test_rechunk_idat() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let stream = png.idat_data();
+        let count = |png: &Png| {
+            png.chunks()
+                .iter()
+                .filter(|c| c.chunk_type().to_string() == "IDAT")
+                .count()
+        };
+
+        png.rechunk_idat(1000).unwrap();
+        assert_eq!(count(&png), 5);
+        assert_eq!(png.idat_data(), stream);
+        assert_eq!(&png.chunks()[4].chunk_type().to_string(), "IDAT");
+
+        png.rechunk_idat(i32::MAX as usize).unwrap();
+        assert_eq!(count(&png), 1);
+        assert_eq!(png.idat_data(), stream);
+
+        let reparsed = Png::try_from(&png.as_bytes()[..]).unwrap();
+        assert_eq!(reparsed.idat_data(), stream);
+        assert!(png.rechunk_idat(0).is_err());
+    }
+
+    #[test]
+    fn test_append_chunk_goes_before_iend() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.append_chunk(
+            Chunk::chunk_from_strings("TeSt".to_string(), "Message".to_string()).unwrap(),
+        );
+        let chunks = png.chunks();
+        assert_eq!(&chunks[chunks.len() - 2].chunk_type().to_string(), "TeSt");
+        assert_eq!(&chunks[chunks.len() - 1].chunk_type().to_string(), "IEND");
+    }
+
+    #[test]
+    fn test_trailing_data() {
+        let zip = b"PK\x03\x04 not a chunk at all";
+        let bytes: Vec<u8> = PNG_FILE.iter().chain(zip.iter()).copied().collect();
+
+        let mut png = Png::try_from(&bytes[..]).unwrap();
+        assert_eq!(png.trailing_data(), zip);
+        assert_eq!(png.chunks().len(), 7);
+        assert_eq!(png.as_bytes(), bytes);
+
+        assert_eq!(png.strip_trailing_data(), zip);
+        assert_eq!(png.as_bytes(), PNG_FILE);
+    }
+
+    #[test]
+    fn test_chunks_after_iend_are_trailing_data() {
+        let extra = Chunk::chunk_from_strings("ruSt".to_string(), "late".to_string()).unwrap();
+        let bytes: Vec<u8> = PNG_FILE.iter().copied().chain(extra.as_bytes()).collect();
+
+        let png = Png::try_from(&bytes[..]).unwrap();
+        assert!(png.chunk_by_type("ruSt").is_none());
+        assert_eq!(png.trailing_data(), extra.as_bytes());
+    }
+
+    #[test]
+    fn test_adopt_trailing_chunks() {
+        // The layout written before chunks were inserted ahead of IEND.
+        let extra = Chunk::chunk_from_strings("ruSt".to_string(), "late".to_string()).unwrap();
+        let bytes: Vec<u8> = PNG_FILE.iter().copied().chain(extra.as_bytes()).collect();
+
+        let mut png = Png::try_from(&bytes[..]).unwrap();
+        assert_eq!(png.adopt_trailing_chunks(), 1);
+        assert!(png.trailing_data().is_empty());
+        let chunks = png.chunks();
+        assert_eq!(&chunks[chunks.len() - 2].data_as_string().unwrap(), "late");
+        assert_eq!(&chunks[chunks.len() - 1].chunk_type().to_string(), "IEND");
+
+        let zip = b"PK\x03\x04 an appended archive".to_vec();
+        let bytes: Vec<u8> = PNG_FILE.iter().copied().chain(zip.clone()).collect();
+        let mut png = Png::try_from(&bytes[..]).unwrap();
+        assert_eq!(png.adopt_trailing_chunks(), 0);
+        assert_eq!(png.trailing_data(), zip);
+    }
+
     #[test]
     fn test_png_from_image_file() {
         let png = Png::try_from(&PNG_FILE[..]);
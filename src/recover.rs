@@ -0,0 +1,323 @@
+use std::str::FromStr;
+
+use crate::{
+    chunk::{make_crc, Chunk},
+    chunk_type::ChunkType,
+    png::Png,
+};
+
+/// A stretch of the file that could not be read as an intact chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Damage {
+    pub offset: usize,
+    pub len: usize,
+    pub message: String,
+}
+
+/// What a lenient parse salvaged from a damaged file.
+pub struct Recovery {
+    /// The intact chunks, and the bytes after IEND as trailing data.
+    pub png: Png,
+    pub damage: Vec<Damage>,
+    pub found_iend: bool,
+}
+
+impl Recovery {
+    /// The recovered chunks with IHDR first and a fresh IEND at the end.
+    pub fn repaired(&self) -> crate::Result<Png> {
+        let chunks = self.png.chunks();
+        if chunks
+            .first()
+            .is_none_or(|c| c.chunk_type().to_string() != "IHDR")
+        {
+            return Err("the IHDR chunk is lost, the image cannot be repaired".into());
+        }
+
+        let mut chunks: Vec<Chunk> = chunks
+            .into_iter()
+            .filter(|c| c.chunk_type().to_string() != "IEND")
+            .collect();
+        chunks.push(Chunk::new(ChunkType::from_str("IEND")?, Vec::new()));
+        let mut png = Png::from_chunks(chunks);
+        png.set_trailing_data(self.png.trailing_data().to_vec());
+        Ok(png)
+    }
+}
+
+/// Length, type and data of a chunk at `at` whose type is made of letters
+/// and whose length fits in `bytes`, whatever its CRC.
+fn framed_chunk_at(bytes: &[u8], at: usize) -> Option<(ChunkType, &[u8], usize)> {
+    let header = bytes.get(at..at.checked_add(8)?)?;
+    let len = u32::from_be_bytes(header[0..4].try_into().unwrap()) as usize;
+    let chunk_type = ChunkType::try_from(<[u8; 4]>::try_from(&header[4..8]).unwrap()).ok()?;
+    if len > Chunk::MAX_LENGTH as usize {
+        return None;
+    }
+    let end = at.checked_add(12)?.checked_add(len)?;
+    if end > bytes.len() {
+        return None;
+    }
+    Some((chunk_type, &bytes[at + 8..end - 4], end))
+}
+
+/// How many times the file size may be spent on chunk data whose CRC turns
+/// out wrong. Every offset after damage is tried as a chunk, so without a
+/// budget a file full of plausible chunk headers would take quadratic time.
+const CRC_BUDGET_FACTOR: usize = 4;
+
+/// Like `framed_chunk_at`, but only when the CRC is right. Data whose CRC is
+/// wrong is taken from `budget`, and a chunk larger than what is left is not
+/// checked at all. Nothing is copied.
+fn intact_frame_at<'a>(
+    bytes: &'a [u8],
+    at: usize,
+    budget: &mut usize,
+) -> Option<(ChunkType, &'a [u8], usize)> {
+    let (chunk_type, data, end) = framed_chunk_at(bytes, at)?;
+    if data.len() > *budget {
+        return None;
+    }
+    let crc = u32::from_be_bytes(bytes[end - 4..end].try_into().unwrap());
+    if make_crc(&chunk_type, data) != crc {
+        *budget -= data.len();
+        return None;
+    }
+    Some((chunk_type, data, end))
+}
+
+/// The chunk at `at` and the offset after it, if its CRC is right.
+fn intact_chunk_at(bytes: &[u8], at: usize, budget: &mut usize) -> Option<(Chunk, usize)> {
+    let (chunk_type, data, end) = intact_frame_at(bytes, at, budget)?;
+    Some((Chunk::new(chunk_type, data.to_vec()), end))
+}
+
+fn resyncs_at(bytes: &[u8], at: usize, budget: &mut usize) -> bool {
+    intact_frame_at(bytes, at, budget).is_some()
+}
+
+/// Reads every intact chunk of a damaged PNG. After unreadable bytes the
+/// parser resynchronizes on the next chunk with a valid CRC. A chunk whose
+/// framing is sound but whose CRC is wrong is dropped, unless
+/// `keep_bad_crc` is set, in which case it is kept with a corrected CRC.
+pub fn recover(bytes: &[u8], keep_bad_crc: bool) -> Recovery {
+    let mut damage = Vec::new();
+    let mut chunks = Vec::new();
+    let mut found_iend = false;
+    let mut budget = bytes.len().saturating_mul(CRC_BUDGET_FACTOR);
+
+    if !bytes.starts_with(&Png::STANDARD_HEADER) {
+        damage.push(Damage {
+            offset: 0,
+            len: Png::STANDARD_HEADER.len().min(bytes.len()),
+            message: "damaged PNG signature".to_string(),
+        });
+    }
+
+    let mut at = Png::STANDARD_HEADER.len().min(bytes.len());
+    while at < bytes.len() {
+        if let Some((chunk, end)) = intact_chunk_at(bytes, at, &mut budget) {
+            found_iend = chunk.chunk_type().to_string() == "IEND";
+            chunks.push(chunk);
+            at = end;
+            if found_iend {
+                break;
+            }
+            continue;
+        }
+
+        // A bad CRC is only trusted when an intact chunk, or the end of a
+        // truncated file, follows; otherwise the length may be damaged.
+        if let Some((chunk_type, data, end)) = framed_chunk_at(bytes, at)
+            .filter(|(_, _, end)| end + 12 > bytes.len() || resyncs_at(bytes, *end, &mut budget))
+        {
+            damage.push(Damage {
+                offset: at,
+                len: end - at,
+                message: format!("CRC mismatch in {chunk_type} chunk"),
+            });
+            if keep_bad_crc {
+                chunks.push(Chunk::new(chunk_type, data.to_vec()));
+            }
+            at = end;
+            continue;
+        }
+
+        let next = (at + 1..bytes.len()).find(|&p| resyncs_at(bytes, p, &mut budget));
+        let skipped = next.unwrap_or(bytes.len()) - at;
+        damage.push(Damage {
+            offset: at,
+            len: skipped,
+            message: match next {
+                Some(_) => format!("{skipped} unreadable bytes"),
+                None => format!("{skipped} unreadable bytes at the end of the file"),
+            },
+        });
+        at += skipped;
+    }
+
+    if !found_iend {
+        damage.push(Damage {
+            offset: bytes.len(),
+            len: 0,
+            message: "no IEND chunk, the file is truncated".to_string(),
+        });
+    }
+
+    let mut png = Png::from_chunks(chunks);
+    png.set_trailing_data(bytes[at..].to_vec());
+    Recovery {
+        png,
+        damage,
+        found_iend,
+    }
+}
+
+/// A chunk whose stored CRC disagrees with its type and data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrcMismatch {
+    pub offset: usize,
+    pub chunk_type: ChunkType,
+    pub stored: u32,
+    pub computed: u32,
+}
+
+/// Rewrites the wrong CRCs of a file whose chunk framing is intact, leaving
+/// every other byte as it is. Returns the fixed file and the chunks changed.
+pub fn fix_crcs(bytes: &[u8]) -> crate::Result<(Vec<u8>, Vec<CrcMismatch>)> {
+    if !bytes.starts_with(&Png::STANDARD_HEADER) {
+        return Err("not a PNG file".into());
+    }
+
+    let mut fixed = bytes.to_vec();
+    let mut mismatches = Vec::new();
+    let mut at = Png::STANDARD_HEADER.len();
+    while at < bytes.len() {
+        let (chunk_type, data, end) = framed_chunk_at(bytes, at).ok_or_else(|| {
+            format!("the chunk at offset {at} is damaged beyond its CRC, try repair")
+        })?;
+        let stored = u32::from_be_bytes(bytes[end - 4..end].try_into().unwrap());
+        let computed = make_crc(&chunk_type, data);
+        if stored != computed {
+            fixed[end - 4..end].copy_from_slice(&computed.to_be_bytes());
+            mismatches.push(CrcMismatch {
+                offset: at,
+                chunk_type: chunk_type.clone(),
+                stored,
+                computed,
+            });
+        }
+        at = end;
+        if chunk_type.to_string() == "IEND" {
+            break;
+        }
+    }
+    Ok((fixed, mismatches))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::Encoder;
+    use crate::ihdr::{ColorType, Ihdr};
+
+    fn testing_bytes() -> Vec<u8> {
+        let mut png = Encoder::new(Ihdr::new(8, 8, ColorType::Grayscale, 8).unwrap())
+            .with_idat_size(20)
+            .encode(&(0..64).collect::<Vec<u8>>())
+            .unwrap();
+        png.append_chunk(Chunk::chunk_from_strings("tEXt".into(), "Comment\0hi".into()).unwrap());
+        png.as_bytes()
+    }
+
+    #[test]
+    fn test_intact_file() {
+        let bytes = testing_bytes();
+        let recovery = recover(&bytes, false);
+        assert!(recovery.damage.is_empty());
+        assert!(recovery.found_iend);
+        assert_eq!(recovery.png.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_resynchronizes_after_damage() {
+        let mut bytes = testing_bytes();
+        let intact = Png::try_from(&bytes[..]).unwrap().chunks().len();
+        // Corrupt the length of the first IDAT chunk.
+        let idat = 8 + 25;
+        bytes[idat] = 0xff;
+
+        let recovery = recover(&bytes, false);
+        assert_eq!(recovery.png.chunks().len(), intact - 1);
+        assert_eq!(recovery.damage.len(), 1);
+        assert_eq!(recovery.damage[0].offset, idat);
+        assert!(recovery.found_iend);
+    }
+
+    #[test]
+    fn test_bad_crc_and_truncation() {
+        let bytes = testing_bytes();
+        let mut damaged = bytes[..bytes.len() - 12].to_vec();
+        // Flip a data byte of the tEXt chunk.
+        let text = damaged.len() - 6;
+        damaged[text] ^= 1;
+
+        let recovery = recover(&damaged, false);
+        let messages: Vec<&str> = recovery.damage.iter().map(|d| &d.message[..]).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "CRC mismatch in tEXt chunk",
+                "no IEND chunk, the file is truncated"
+            ]
+        );
+        assert!(recovery.png.chunk_by_type("tEXt").is_none());
+
+        let kept = recover(&damaged, true);
+        assert!(kept.png.chunk_by_type("tEXt").is_some());
+        let repaired = kept.repaired().unwrap();
+        assert!(recover(&repaired.as_bytes(), false).damage.is_empty());
+    }
+
+    #[test]
+    fn test_resync_takes_linear_time() {
+        // Every 8 bytes claim a 1 MiB chunk whose CRC is wrong.
+        let mut bytes = Png::STANDARD_HEADER.to_vec();
+        while bytes.len() < 4 << 20 {
+            bytes.extend(b"\x00\x10\x00\x00AAAA");
+        }
+
+        let started = std::time::Instant::now();
+        let recovery = recover(&bytes, true);
+        assert!(started.elapsed().as_secs() < 10);
+        assert!(recovery.png.chunks().is_empty());
+        assert_eq!(
+            recovery.damage[0].message,
+            format!(
+                "{} unreadable bytes at the end of the file",
+                bytes.len() - 8
+            )
+        );
+    }
+
+    #[test]
+    fn test_fix_crcs() {
+        let bytes = testing_bytes();
+        let mut damaged = bytes.clone();
+        // The CRC of IHDR, then the data of the last IDAT.
+        damaged[8 + 21] ^= 0xff;
+        damaged[bytes.len() - 40] ^= 0xff;
+        damaged.extend(b"trailing");
+
+        let (fixed, mismatches) = fix_crcs(&damaged).unwrap();
+        let types: Vec<String> = mismatches
+            .iter()
+            .map(|m| m.chunk_type.to_string())
+            .collect();
+        assert_eq!(types, vec!["IHDR", "IDAT"]);
+        assert_eq!(mismatches[0].offset, 8);
+        assert_eq!(fixed[..8 + 25], bytes[..8 + 25]);
+        assert!(fixed.ends_with(b"trailing"));
+        assert!(Png::try_from(&fixed[..]).is_ok());
+        assert!(fix_crcs(&fixed).unwrap().1.is_empty());
+    }
+}
@@ -0,0 +1,455 @@
+use std::{fmt::Display, io::Read};
+
+use flate2::read::ZlibDecoder;
+
+use crate::{
+    apng::{AnimationControl, FrameControl, FrameData},
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    ihdr::Ihdr,
+};
+
+/// How many chunks of a type a PNG may hold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Multiplicity {
+    One,
+    AtMostOne,
+    OneOrMore,
+    Any,
+}
+
+impl Display for Multiplicity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Multiplicity::One => "exactly one",
+            Multiplicity::AtMostOne => "at most one",
+            Multiplicity::OneOrMore => "one or more",
+            Multiplicity::Any => "any number",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Where a chunk may appear relative to the critical chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placement {
+    First,
+    Last,
+    BeforePlte,
+    /// After PLTE, when there is one, and before IDAT.
+    BetweenPlteAndIdat,
+    BeforeIdat,
+    Anywhere,
+}
+
+impl Display for Placement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Placement::First => "first",
+            Placement::Last => "last",
+            Placement::BeforePlte => "before PLTE and IDAT",
+            Placement::BetweenPlteAndIdat => "after PLTE, before IDAT",
+            Placement::BeforeIdat => "before IDAT",
+            Placement::Anywhere => "anywhere after IHDR",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Summarizes chunk data, or returns `None` when it is malformed.
+pub type Decoder = fn(&[u8]) -> Option<String>;
+
+/// What the specifications say about a registered chunk type.
+pub struct ChunkInfo {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Section of the PNG specification, third edition, or the document
+    /// defining the chunk.
+    pub spec: &'static str,
+    pub multiplicity: Multiplicity,
+    pub placement: Placement,
+    pub decode: Option<Decoder>,
+}
+
+const fn info(
+    name: &'static str,
+    description: &'static str,
+    spec: &'static str,
+    multiplicity: Multiplicity,
+    placement: Placement,
+    decode: Option<Decoder>,
+) -> ChunkInfo {
+    ChunkInfo {
+        name,
+        description,
+        spec,
+        multiplicity,
+        placement,
+        decode,
+    }
+}
+
+use Multiplicity::*;
+use Placement::*;
+
+/// Chunk types defined by the PNG specification and its registered
+/// extensions, including APNG.
+#[rustfmt::skip]
+pub static KNOWN_CHUNKS: [ChunkInfo; 30] = [
+    info("IHDR", "image header", "PNG 11.2.2", One, First, Some(decode_ihdr)),
+    info("PLTE", "palette", "PNG 11.2.3", AtMostOne, BeforeIdat, Some(decode_plte)),
+    info("IDAT", "image data", "PNG 11.2.4", OneOrMore, Anywhere, None),
+    info("IEND", "image trailer", "PNG 11.2.5", One, Last, None),
+    info("tRNS", "transparency", "PNG 11.3.2.1", AtMostOne, BetweenPlteAndIdat, None),
+    info("cHRM", "primary chromaticities and white point", "PNG 11.3.3.1", AtMostOne, BeforePlte, None),
+    info("gAMA", "image gamma", "PNG 11.3.3.2", AtMostOne, BeforePlte, Some(decode_gama)),
+    info("iCCP", "embedded ICC profile", "PNG 11.3.3.3", AtMostOne, BeforePlte, None),
+    info("sBIT", "significant bits", "PNG 11.3.3.4", AtMostOne, BeforePlte, None),
+    info("sRGB", "standard RGB colour space", "PNG 11.3.3.5", AtMostOne, BeforePlte, Some(decode_srgb)),
+    info("cICP", "coding-independent code points", "PNG 11.3.3.6", AtMostOne, BeforePlte, None),
+    info("mDCV", "mastering display colour volume", "PNG 11.3.3.7", AtMostOne, BeforePlte, None),
+    info("cLLI", "content light level", "PNG 11.3.3.8", AtMostOne, BeforePlte, None),
+    info("tEXt", "Latin-1 text", "PNG 11.3.4.3", Any, Anywhere, Some(decode_text)),
+    info("zTXt", "compressed Latin-1 text", "PNG 11.3.4.4", Any, Anywhere, Some(decode_ztxt)),
+    info("iTXt", "international text", "PNG 11.3.4.5", Any, Anywhere, Some(decode_itxt)),
+    info("bKGD", "background colour", "PNG 11.3.5.1", AtMostOne, BetweenPlteAndIdat, None),
+    info("hIST", "palette histogram", "PNG 11.3.5.2", AtMostOne, BetweenPlteAndIdat, None),
+    info("pHYs", "physical pixel dimensions", "PNG 11.3.5.3", AtMostOne, BeforeIdat, Some(decode_phys)),
+    info("sPLT", "suggested palette", "PNG 11.3.5.4", Any, BeforeIdat, None),
+    info("eXIf", "Exif metadata", "PNG 11.3.5.5", AtMostOne, BeforeIdat, None),
+    info("tIME", "last modification time", "PNG 11.3.6.1", AtMostOne, Anywhere, Some(decode_time)),
+    info("acTL", "animation control", "PNG 11.3.7.1", AtMostOne, BeforeIdat, Some(decode_actl)),
+    info("fcTL", "frame control", "PNG 11.3.7.2", Any, Anywhere, Some(decode_fctl)),
+    info("fdAT", "frame data", "PNG 11.3.7.3", Any, Anywhere, Some(decode_fdat)),
+    info("oFFs", "image offset", "PNG extensions", AtMostOne, BeforeIdat, None),
+    info("pCAL", "calibration of pixel values", "PNG extensions", AtMostOne, BeforeIdat, None),
+    info("sCAL", "physical scale of image subject", "PNG extensions", AtMostOne, BeforeIdat, None),
+    info("sTER", "stereo image indicator", "PNG extensions", AtMostOne, BeforeIdat, None),
+    info("dSIG", "digital signature", "PNG extensions", Any, Anywhere, None),
+];
+
+pub fn lookup(chunk_type: &ChunkType) -> Option<&'static ChunkInfo> {
+    let name = chunk_type.to_string();
+    KNOWN_CHUNKS.iter().find(|info| info.name == name)
+}
+
+/// A summary of `chunk`'s data for registered types with a decoder.
+pub fn describe(chunk: &Chunk) -> Option<String> {
+    lookup(chunk.chunk_type())?.decode?(chunk.data())
+}
+
+/// Problems with the overall structure: IHDR not first or repeated, no
+/// IEND, and IDAT chunks that are not consecutive.
+pub fn structure_problems(chunks: &[Chunk]) -> Vec<String> {
+    let types: Vec<String> = chunks.iter().map(|c| c.chunk_type().to_string()).collect();
+    let mut problems = Vec::new();
+
+    if types.first().map(|t| &t[..]) != Some("IHDR") {
+        problems.push("IHDR is not the first chunk".to_string());
+    }
+    if types.iter().filter(|t| *t == "IHDR").count() > 1 {
+        problems.push("more than one IHDR chunk".to_string());
+    }
+    if !types.iter().any(|t| t == "IEND") {
+        problems.push("no IEND chunk".to_string());
+    }
+
+    let idats: Vec<usize> = (0..types.len()).filter(|&i| types[i] == "IDAT").collect();
+    if let (Some(&start), Some(&end)) = (idats.first(), idats.last()) {
+        let between: Vec<&String> = types[start..end].iter().filter(|t| *t != "IDAT").collect();
+        if !between.is_empty() {
+            problems.push(format!("chunks between IDAT chunks: {between:?}"));
+        }
+    }
+    problems
+}
+
+/// Registered chunks that appear too often or in the wrong place.
+pub fn placement_problems(chunks: &[Chunk]) -> Vec<String> {
+    let types: Vec<String> = chunks.iter().map(|c| c.chunk_type().to_string()).collect();
+    let plte = types.iter().position(|t| t == "PLTE");
+    let idat = types.iter().position(|t| t == "IDAT");
+    let mut problems = Vec::new();
+
+    for info in KNOWN_CHUNKS.iter() {
+        if matches!(info.placement, First | Last) {
+            continue;
+        }
+        let positions: Vec<usize> = (0..types.len())
+            .filter(|&i| types[i] == info.name)
+            .collect();
+        if positions.len() > 1 && matches!(info.multiplicity, One | AtMostOne) {
+            problems.push(format!("more than one {} chunk", info.name));
+        }
+
+        let after_idat = |i: usize| idat.is_some_and(|d| i > d);
+        let misplaced = positions.iter().any(|&i| match info.placement {
+            BeforePlte => plte.is_some_and(|p| i > p) || after_idat(i),
+            BetweenPlteAndIdat => plte.is_some_and(|p| i < p) || after_idat(i),
+            BeforeIdat => after_idat(i),
+            First | Last | Anywhere => false,
+        });
+        if misplaced {
+            problems.push(format!("{} is out of order", info.name));
+        }
+    }
+    problems
+}
+
+/// Every way `chunks` break the rules of the registry, including critical
+/// chunks that no decoder would understand.
+pub fn validate(chunks: &[Chunk]) -> Vec<String> {
+    let mut problems = structure_problems(chunks);
+    problems.extend(placement_problems(chunks));
+    for chunk in chunks.iter() {
+        let chunk_type = chunk.chunk_type();
+        if chunk_type.is_critical() && lookup(chunk_type).is_none() {
+            problems.push(format!("unknown critical chunk {chunk_type}"));
+        }
+        if !chunk_type.is_reserved_bit_valid() {
+            problems.push(format!("{chunk_type} has the reserved bit set"));
+        }
+    }
+    problems
+}
+
+fn decode_ihdr(data: &[u8]) -> Option<String> {
+    let ihdr = Ihdr::try_from(data).ok()?;
+    let interlaced = if ihdr.is_interlaced() {
+        ", interlaced"
+    } else {
+        ""
+    };
+    Some(format!(
+        "{}x{}, {} at {} bits{interlaced}",
+        ihdr.width(),
+        ihdr.height(),
+        ihdr.color_type(),
+        ihdr.bit_depth()
+    ))
+}
+
+fn decode_plte(data: &[u8]) -> Option<String> {
+    data.len()
+        .is_multiple_of(3)
+        .then(|| format!("{} entries", data.len() / 3))
+}
+
+fn decode_gama(data: &[u8]) -> Option<String> {
+    let gamma = u32::from_be_bytes(data.try_into().ok()?);
+    Some(format!("gamma {:.5}", gamma as f64 / 100_000.0))
+}
+
+fn decode_srgb(data: &[u8]) -> Option<String> {
+    let intent = match data {
+        [0] => "perceptual",
+        [1] => "relative colorimetric",
+        [2] => "saturation",
+        [3] => "absolute colorimetric",
+        _ => return None,
+    };
+    Some(format!("{intent} rendering intent"))
+}
+
+fn decode_phys(data: &[u8]) -> Option<String> {
+    if data.len() != 9 {
+        return None;
+    }
+    let x = u32::from_be_bytes(data[0..4].try_into().unwrap());
+    let y = u32::from_be_bytes(data[4..8].try_into().unwrap());
+    match data[8] {
+        1 => Some(format!("{x}x{y} pixels per metre")),
+        _ => Some(format!("{x}:{y} pixel aspect ratio")),
+    }
+}
+
+fn decode_time(data: &[u8]) -> Option<String> {
+    if data.len() != 7 {
+        return None;
+    }
+    let year = u16::from_be_bytes([data[0], data[1]]);
+    Some(format!(
+        "{year:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        data[2], data[3], data[4], data[5], data[6]
+    ))
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+/// Compressed text is shown up to this many bytes, so a small chunk cannot
+/// inflate to fill memory.
+const INFLATE_LIMIT: usize = 64 * 1024;
+
+/// Inflates at most `INFLATE_LIMIT` bytes, and tells whether there was more.
+fn inflate(data: &[u8]) -> Option<(Vec<u8>, bool)> {
+    let mut out = Vec::new();
+    ZlibDecoder::new(data)
+        .take(INFLATE_LIMIT as u64 + 1)
+        .read_to_end(&mut out)
+        .ok()?;
+    let truncated = out.len() > INFLATE_LIMIT;
+    out.truncate(INFLATE_LIMIT);
+    Some((out, truncated))
+}
+
+fn truncation_note(truncated: bool) -> &'static str {
+    match truncated {
+        true => " [truncated]",
+        false => "",
+    }
+}
+
+fn decode_text(data: &[u8]) -> Option<String> {
+    let (keyword, text) = data.split_at(data.iter().position(|&b| b == 0)?);
+    Some(format!("{}: {}", latin1(keyword), latin1(&text[1..])))
+}
+
+fn decode_ztxt(data: &[u8]) -> Option<String> {
+    let (keyword, rest) = data.split_at(data.iter().position(|&b| b == 0)?);
+    let (text, truncated) = inflate(rest.get(2..)?)?;
+    Some(format!(
+        "{}: {}{}",
+        latin1(keyword),
+        latin1(&text),
+        truncation_note(truncated)
+    ))
+}
+
+fn decode_itxt(data: &[u8]) -> Option<String> {
+    let mut fields = data.splitn(2, |&b| b == 0);
+    let keyword = fields.next()?;
+    let rest = fields.next()?;
+    let (compressed, rest) = (*rest.first()?, rest.get(2..)?);
+    // Language tag, then the translated keyword.
+    let mut fields = rest.splitn(3, |&b| b == 0);
+    let language = fields.next()?;
+    let _translated = fields.next()?;
+    let text = fields.next()?;
+    let (text, truncated) = match compressed {
+        0 => (text.to_vec(), false),
+        _ => inflate(text)?,
+    };
+    // The cut may fall inside a character; drop its first bytes.
+    let text = match std::str::from_utf8(&text) {
+        Ok(text) => text,
+        Err(e) if truncated && e.error_len().is_none() => {
+            std::str::from_utf8(&text[..e.valid_up_to()]).ok()?
+        }
+        Err(_) => return None,
+    };
+    let language = match language {
+        [] => String::new(),
+        tag => format!(" [{}]", latin1(tag)),
+    };
+    Some(format!(
+        "{}{language}: {text}{}",
+        latin1(keyword),
+        truncation_note(truncated)
+    ))
+}
+
+fn decode_actl(data: &[u8]) -> Option<String> {
+    let control = AnimationControl::try_from(data).ok()?;
+    match control.num_plays {
+        0 => Some(format!("{} frames, looping forever", control.num_frames)),
+        n => Some(format!("{} frames, played {n} times", control.num_frames)),
+    }
+}
+
+fn decode_fctl(data: &[u8]) -> Option<String> {
+    let control = FrameControl::try_from(data).ok()?;
+    Some(format!(
+        "sequence {}, {}x{} at {},{}, {} ms",
+        control.sequence_number,
+        control.width,
+        control.height,
+        control.x_offset,
+        control.y_offset,
+        control.delay().as_millis()
+    ))
+}
+
+fn decode_fdat(data: &[u8]) -> Option<String> {
+    let frame_data = FrameData::try_from(data).ok()?;
+    Some(format!(
+        "sequence {}, {} bytes",
+        frame_data.sequence_number,
+        frame_data.data.len()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    #[test]
+    fn test_registry_entries_are_valid() {
+        for info in KNOWN_CHUNKS.iter() {
+            let chunk_type = ChunkType::from_str(info.name).unwrap();
+            assert!(chunk_type.is_valid(), "{}", info.name);
+            assert_eq!(lookup(&chunk_type).unwrap().name, info.name);
+        }
+        assert!(lookup(&ChunkType::from_str("ruSt").unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(
+            describe(&chunk("tEXt", b"Title\0Sunset")).as_deref(),
+            Some("Title: Sunset")
+        );
+        assert_eq!(
+            describe(&chunk("iTXt", b"Title\0\0\0fr\0Titre\0Coucher")).as_deref(),
+            Some("Title [fr]: Coucher")
+        );
+        assert_eq!(
+            describe(&chunk("tIME", &[7, 234, 10, 18, 9, 5, 0])).as_deref(),
+            Some("2026-10-18 09:05:00 UTC")
+        );
+        assert_eq!(describe(&chunk("gAMA", &[1, 2])), None);
+        assert_eq!(describe(&chunk("IDAT", &[1, 2])), None);
+    }
+
+    #[test]
+    fn test_describe_caps_inflated_text() {
+        use flate2::{write::ZlibEncoder, Compression};
+        use std::io::Write;
+
+        let mut encoder = ZlibEncoder::new(b"Bomb\0\0".to_vec(), Compression::best());
+        encoder.write_all(&vec![b'a'; 10 << 20]).unwrap();
+        let description = describe(&chunk("zTXt", &encoder.finish().unwrap())).unwrap();
+        assert!(description.ends_with(" [truncated]"));
+        assert_eq!(description.len(), "Bomb: ".len() + INFLATE_LIMIT + 12);
+    }
+
+    #[test]
+    fn test_validate() {
+        let chunks = [
+            chunk("IHDR", &[0; 13]),
+            chunk("PLTE", &[0; 3]),
+            chunk("gAMA", &[0; 4]),
+            chunk("IDAT", &[]),
+            chunk("pHYs", &[0; 9]),
+            chunk("tIME", &[0; 7]),
+            chunk("tIME", &[0; 7]),
+            chunk("ZZZZ", &[]),
+            chunk("IEND", &[]),
+        ];
+        assert_eq!(
+            validate(&chunks),
+            vec![
+                "gAMA is out of order",
+                "pHYs is out of order",
+                "more than one tIME chunk",
+                "unknown critical chunk ZZZZ",
+            ]
+        );
+    }
+}
@@ -0,0 +1,368 @@
+use std::str::FromStr;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand::rngs::OsRng;
+use sha2::{Digest, Sha256};
+
+use crate::{chunk::Chunk, chunk_type::ChunkType, png::Png};
+
+/// Private, ancillary and unsafe-to-copy, so editors that do not know it drop
+/// it as soon as they touch the image.
+pub const SIGNATURE_CHUNK: &str = "siGN";
+
+const VERSION: u8 = 1;
+const DOMAIN: &[u8] = b"pngme signature v1\0";
+const DIGEST_LEN: usize = 32;
+const ENTRY_LEN: usize = 4 + DIGEST_LEN;
+
+/// One chunk covered by a signature: its type and the SHA-256 of its data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoveredChunk {
+    pub chunk_type: ChunkType,
+    pub digest: [u8; DIGEST_LEN],
+}
+
+/// The contents of a signature chunk, or of a detached signature file.
+#[derive(Debug, Clone)]
+pub struct PngSignature {
+    public_key: VerifyingKey,
+    included: Vec<ChunkType>,
+    covered: Vec<CoveredChunk>,
+    signature: Signature,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoverageStatus {
+    Unchanged,
+    Changed,
+    Missing,
+    /// A chunk of a covered type that was not there when signing.
+    Added,
+}
+
+pub struct Verification {
+    pub public_key: VerifyingKey,
+    /// Whether the signature over the recorded digests is valid.
+    pub signature_valid: bool,
+    pub chunks: Vec<(ChunkType, CoverageStatus)>,
+}
+
+impl Verification {
+    pub fn is_intact(&self) -> bool {
+        self.signature_valid
+            && self
+                .chunks
+                .iter()
+                .all(|(_, status)| *status == CoverageStatus::Unchanged)
+    }
+}
+
+impl TryFrom<&[u8]> for PngSignature {
+    type Error = crate::Error;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let truncated = || -> crate::Error { "truncated signature".into() };
+        if value.len() < 1 + 32 + 1 {
+            return Err(truncated());
+        }
+        if value[0] != VERSION {
+            return Err("unsupported signature version".into());
+        }
+
+        let public_key = VerifyingKey::from_bytes(value[1..33].try_into().unwrap())?;
+        let included_count = value[33] as usize;
+        let mut at = 34;
+
+        let mut included = Vec::with_capacity(included_count);
+        for _ in 0..included_count {
+            let bytes: [u8; 4] = value
+                .get(at..at + 4)
+                .ok_or_else(truncated)?
+                .try_into()
+                .unwrap();
+            included.push(ChunkType::try_from(bytes)?);
+            at += 4;
+        }
+
+        let count_bytes = value.get(at..at + 4).ok_or_else(truncated)?;
+        let covered_count = u32::from_be_bytes(count_bytes.try_into().unwrap()) as usize;
+        at += 4;
+
+        let entries = value
+            .get(at..at + covered_count * ENTRY_LEN)
+            .ok_or_else(truncated)?;
+        let mut covered = Vec::with_capacity(covered_count);
+        for entry in entries.chunks(ENTRY_LEN) {
+            covered.push(CoveredChunk {
+                chunk_type: ChunkType::try_from(<[u8; 4]>::try_from(&entry[..4]).unwrap())?,
+                digest: entry[4..].try_into().unwrap(),
+            });
+        }
+        at += covered_count * ENTRY_LEN;
+
+        let signature_bytes: [u8; 64] = value
+            .get(at..)
+            .filter(|rest| rest.len() == 64)
+            .ok_or_else(truncated)?
+            .try_into()
+            .unwrap();
+
+        Ok(PngSignature {
+            public_key,
+            included,
+            covered,
+            signature: Signature::from_bytes(&signature_bytes),
+        })
+    }
+}
+
+impl PngSignature {
+    pub fn public_key(&self) -> &VerifyingKey {
+        &self.public_key
+    }
+
+    pub fn covered(&self) -> &[CoveredChunk] {
+        &self.covered
+    }
+
+    fn signed_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![VERSION];
+        bytes.extend(self.public_key.as_bytes());
+        bytes.push(self.included.len() as u8);
+        for chunk_type in self.included.iter() {
+            bytes.extend(chunk_type.bytes());
+        }
+        bytes.extend((self.covered.len() as u32).to_be_bytes());
+        for entry in self.covered.iter() {
+            bytes.extend(entry.chunk_type.bytes());
+            bytes.extend(entry.digest);
+        }
+        bytes
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.signed_bytes();
+        bytes.extend(self.signature.to_bytes());
+        bytes
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new(
+            ChunkType::from_str(SIGNATURE_CHUNK).unwrap(),
+            self.as_bytes(),
+        )
+    }
+}
+
+/// Critical chunks are always covered; ancillary ones only when their type is
+/// listed in `included`. Existing signature chunks never are.
+fn is_covered(chunk: &Chunk, included: &[ChunkType]) -> bool {
+    let chunk_type = chunk.chunk_type();
+    chunk_type.to_string() != SIGNATURE_CHUNK
+        && (chunk_type.is_critical() || included.contains(chunk_type))
+}
+
+fn covered_chunks(png: &Png, included: &[ChunkType]) -> Vec<CoveredChunk> {
+    png.chunks()
+        .iter()
+        .filter(|chunk| is_covered(chunk, included))
+        .map(|chunk| CoveredChunk {
+            chunk_type: chunk.chunk_type().clone(),
+            digest: Sha256::digest(chunk.data()).into(),
+        })
+        .collect()
+}
+
+fn message(signed_bytes: &[u8]) -> Vec<u8> {
+    DOMAIN.iter().chain(signed_bytes.iter()).copied().collect()
+}
+
+pub fn generate_signing_key() -> SigningKey {
+    SigningKey::generate(&mut OsRng)
+}
+
+pub fn signing_key_from_hex(hex_key: &str) -> crate::Result<SigningKey> {
+    let bytes: [u8; 32] = hex::decode(hex_key.trim())?
+        .try_into()
+        .map_err(|_| "an Ed25519 key is 32 bytes")?;
+    Ok(SigningKey::from_bytes(&bytes))
+}
+
+pub fn verifying_key_from_hex(hex_key: &str) -> crate::Result<VerifyingKey> {
+    let bytes: [u8; 32] = hex::decode(hex_key.trim())?
+        .try_into()
+        .map_err(|_| "an Ed25519 key is 32 bytes")?;
+    Ok(VerifyingKey::from_bytes(&bytes)?)
+}
+
+/// Signs the critical chunks of `png` plus the ancillary chunks whose type is
+/// in `included`.
+pub fn sign(png: &Png, key: &SigningKey, included: &[ChunkType]) -> crate::Result<PngSignature> {
+    if included.len() > u8::MAX as usize {
+        return Err("too many included chunk types".into());
+    }
+
+    let mut signature = PngSignature {
+        public_key: key.verifying_key(),
+        included: included.to_vec(),
+        covered: covered_chunks(png, included),
+        signature: Signature::from_bytes(&[0; 64]),
+    };
+    signature.signature = key.sign(&message(&signature.signed_bytes()));
+    Ok(signature)
+}
+
+/// The signature embedded in `png`, if any.
+pub fn embedded_signature(png: &Png) -> crate::Result<Option<PngSignature>> {
+    match png.chunk_by_type(SIGNATURE_CHUNK) {
+        Some(chunk) => Ok(Some(PngSignature::try_from(chunk.data())?)),
+        None => Ok(None),
+    }
+}
+
+/// Checks the signature itself, then compares every recorded chunk digest
+/// against the chunks currently in `png`, in order.
+pub fn verify(png: &Png, signature: &PngSignature) -> Verification {
+    let signature_valid = signature
+        .public_key
+        .verify(&message(&signature.signed_bytes()), &signature.signature)
+        .is_ok();
+
+    let current = covered_chunks(png, &signature.included);
+    let mut chunks = Vec::new();
+    let mut remaining = current.iter().peekable();
+
+    for recorded in signature.covered.iter() {
+        // Chunks of a covered type that appear before the next recorded one
+        // of the same type were added after signing.
+        while let Some(next) = remaining.peek() {
+            if next.chunk_type == recorded.chunk_type {
+                break;
+            }
+            let later = signature
+                .covered
+                .iter()
+                .skip_while(|c| !std::ptr::eq(*c, recorded))
+                .any(|c| c.chunk_type == next.chunk_type);
+            if later {
+                break;
+            }
+            chunks.push((next.chunk_type.clone(), CoverageStatus::Added));
+            remaining.next();
+        }
+
+        match remaining.peek() {
+            Some(next) if next.chunk_type == recorded.chunk_type => {
+                let status = if next.digest == recorded.digest {
+                    CoverageStatus::Unchanged
+                } else {
+                    CoverageStatus::Changed
+                };
+                chunks.push((recorded.chunk_type.clone(), status));
+                remaining.next();
+            }
+            _ => chunks.push((recorded.chunk_type.clone(), CoverageStatus::Missing)),
+        }
+    }
+    for extra in remaining {
+        chunks.push((extra.chunk_type.clone(), CoverageStatus::Added));
+    }
+
+    Verification {
+        public_key: signature.public_key,
+        signature_valid,
+        chunks,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        encoder::Encoder,
+        ihdr::{ColorType, Ihdr},
+    };
+
+    fn testing_png() -> Png {
+        let ihdr = Ihdr::new(4, 4, ColorType::Grayscale, 8).unwrap();
+        let mut png = Encoder::new(ihdr).encode(&[9; 16]).unwrap();
+        png.append_chunk(
+            Chunk::chunk_from_strings("tEXt".to_string(), "Author\0me".to_string()).unwrap(),
+        );
+        png
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = generate_signing_key();
+        let mut png = testing_png();
+        let signature = sign(&png, &key, &[]).unwrap();
+        png.append_chunk(signature.to_chunk());
+
+        let embedded = embedded_signature(&png).unwrap().unwrap();
+        let verification = verify(&png, &embedded);
+        assert!(verification.is_intact());
+        assert_eq!(verification.chunks.len(), 3);
+        assert_eq!(verification.public_key, key.verifying_key());
+    }
+
+    #[test]
+    fn test_changed_chunks_are_reported() {
+        let key = generate_signing_key();
+        let png = testing_png();
+        let included = [ChunkType::from_str("tEXt").unwrap()];
+        let signature = sign(&png, &key, &included).unwrap();
+
+        let mut chunks = png.chunks();
+        chunks[1] = Chunk::new(ChunkType::from_str("IDAT").unwrap(), vec![1, 2, 3]);
+        chunks.pop();
+        let tampered = Png::from_chunks(chunks);
+
+        let verification = verify(&tampered, &signature);
+        assert!(verification.signature_valid);
+        assert!(!verification.is_intact());
+        let statuses: Vec<CoverageStatus> =
+            verification.chunks.iter().map(|(_, s)| s.clone()).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                CoverageStatus::Unchanged,
+                CoverageStatus::Changed,
+                CoverageStatus::Unchanged,
+                CoverageStatus::Missing,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_uncovered_chunks_do_not_matter() {
+        let key = generate_signing_key();
+        let mut png = testing_png();
+        let signature = sign(&png, &key, &[]).unwrap();
+        png.append_chunk(
+            Chunk::chunk_from_strings("ruSt".to_string(), "later".to_string()).unwrap(),
+        );
+        assert!(verify(&png, &signature).is_intact());
+    }
+
+    #[test]
+    fn test_forged_signature_is_invalid() {
+        let png = testing_png();
+        let signature = sign(&png, &generate_signing_key(), &[]).unwrap();
+        let mut bytes = signature.as_bytes();
+        bytes[40] ^= 1;
+
+        let forged = PngSignature::try_from(&bytes[..]).unwrap();
+        assert!(!verify(&png, &forged).signature_valid);
+    }
+
+    #[test]
+    fn test_signature_round_trips_through_bytes() {
+        let png = testing_png();
+        let included = [ChunkType::from_str("tEXt").unwrap()];
+        let signature = sign(&png, &generate_signing_key(), &included).unwrap();
+        let parsed = PngSignature::try_from(&signature.as_bytes()[..]).unwrap();
+        assert_eq!(parsed.as_bytes(), signature.as_bytes());
+        assert_eq!(parsed.covered().len(), 4);
+    }
+}
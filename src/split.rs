@@ -0,0 +1,207 @@
+use std::collections::{btree_map::Entry, BTreeMap};
+
+use rand::{rngs::OsRng, RngCore};
+
+/// Marks chunk data as one numbered part of a larger payload.
+pub const MAGIC: [u8; 4] = *b"pmP\x01";
+
+/// Payloads larger than this are split when no part size is given.
+pub const DEFAULT_PART_SIZE: usize = 64 * 1024;
+
+/// Magic, payload id, sequence number and part count.
+const HEADER_LEN: usize = 4 + 8 + 4 + 4;
+
+/// One part of a split payload. `sequence` counts from 0 to `total - 1`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Part {
+    pub id: u64,
+    pub sequence: u32,
+    pub total: u32,
+    pub data: Vec<u8>,
+}
+
+impl TryFrom<&[u8]> for Part {
+    type Error = &'static str;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        if !is_part(value) || value.len() < HEADER_LEN {
+            return Err("not a payload part");
+        }
+        let part = Part {
+            id: u64::from_be_bytes(value[4..12].try_into().unwrap()),
+            sequence: u32::from_be_bytes(value[12..16].try_into().unwrap()),
+            total: u32::from_be_bytes(value[16..20].try_into().unwrap()),
+            data: value[HEADER_LEN..].to_vec(),
+        };
+        if part.sequence >= part.total {
+            return Err("part sequence number is out of range");
+        }
+        Ok(part)
+    }
+}
+
+impl Part {
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(self.id.to_be_bytes());
+        bytes.extend(self.sequence.to_be_bytes());
+        bytes.extend(self.total.to_be_bytes());
+        bytes.extend(self.data.iter());
+        bytes
+    }
+}
+
+pub fn is_part(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+/// Cuts `payload` into parts carrying at most `part_size` payload bytes each,
+/// all tagged with the same random id.
+pub fn split(payload: &[u8], part_size: usize) -> crate::Result<Vec<Part>> {
+    if part_size == 0 {
+        return Err("part size must be at least 1 byte".into());
+    }
+    let total = payload.len().div_ceil(part_size).max(1);
+    let total = u32::try_from(total).map_err(|_| "too many parts")?;
+    let id = OsRng.next_u64();
+
+    Ok((0..total)
+        .map(|sequence| {
+            let start = sequence as usize * part_size;
+            let end = (start + part_size).min(payload.len());
+            Part {
+                id,
+                sequence,
+                total,
+                data: payload[start..end].to_vec(),
+            }
+        })
+        .collect())
+}
+
+/// How many missing sequence numbers `reassemble` lists at most.
+pub const MAX_LISTED_MISSING: usize = 20;
+
+/// The outcome of `reassemble`. `payload` is only set when every part was
+/// found.
+#[derive(Debug)]
+pub struct Reassembly {
+    pub id: u64,
+    pub total: u32,
+    pub payload: Option<Vec<u8>>,
+    /// The first `MAX_LISTED_MISSING` missing sequence numbers.
+    pub missing: Vec<u32>,
+    pub missing_count: u32,
+    /// Sequence numbers seen more than once; the first copy is used.
+    pub duplicates: Vec<u32>,
+    /// Parts belonging to other payloads, which are ignored.
+    pub foreign: usize,
+}
+
+/// Puts the parts of the payload the first part belongs to back in order.
+pub fn reassemble(parts: &[Part]) -> crate::Result<Reassembly> {
+    let first = parts.first().ok_or("no parts to reassemble")?;
+    let (id, total) = (first.id, first.total);
+
+    let mut found: BTreeMap<u32, &Part> = BTreeMap::new();
+    let mut duplicates = Vec::new();
+    let mut foreign = 0;
+    for part in parts.iter() {
+        if part.id != id {
+            foreign += 1;
+        } else if part.total != total {
+            return Err(format!("parts of payload {id:016x} disagree on the part count").into());
+        } else if let Entry::Vacant(entry) = found.entry(part.sequence) {
+            entry.insert(part);
+        } else {
+            duplicates.push(part.sequence);
+        }
+    }
+
+    // `total` comes from the file, so the missing parts are counted rather
+    // than enumerated, and only the first few are listed.
+    let present = found.range(..total).count() as u32;
+    let missing_count = total - present;
+    let missing: Vec<u32> = (0..total)
+        .filter(|s| !found.contains_key(s))
+        .take(MAX_LISTED_MISSING)
+        .collect();
+    let payload =
+        (missing_count == 0).then(|| found.values().flat_map(|part| part.data.clone()).collect());
+
+    Ok(Reassembly {
+        id,
+        total,
+        payload,
+        missing,
+        missing_count,
+        duplicates,
+        foreign,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_and_reassemble() {
+        let payload: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        let mut parts = split(&payload, 300).unwrap();
+        assert_eq!(parts.len(), 4);
+        assert_eq!(parts[3].data.len(), 100);
+
+        parts.reverse();
+        let parsed: Vec<Part> = parts
+            .iter()
+            .map(|p| Part::try_from(&p.as_bytes()[..]).unwrap())
+            .collect();
+        let reassembly = reassemble(&parsed).unwrap();
+        assert_eq!(reassembly.payload.unwrap(), payload);
+        assert!(reassembly.missing.is_empty());
+    }
+
+    #[test]
+    fn test_reassemble_reports_missing_and_duplicates() {
+        let mut parts = split(&[7; 50], 10).unwrap();
+        parts.remove(2);
+        parts.push(parts[0].clone());
+        parts.extend(split(b"other", 10).unwrap());
+
+        let reassembly = reassemble(&parts).unwrap();
+        assert!(reassembly.payload.is_none());
+        assert_eq!(reassembly.total, 5);
+        assert_eq!(reassembly.missing, vec![2]);
+        assert_eq!(reassembly.missing_count, 1);
+        assert_eq!(reassembly.duplicates, vec![0]);
+        assert_eq!(reassembly.foreign, 1);
+    }
+
+    #[test]
+    fn test_reassemble_huge_part_count() {
+        let mut part = split(&[1; 10], 10).unwrap().remove(0);
+        part.total = u32::MAX;
+        let reassembly = reassemble(&[part]).unwrap();
+        assert!(reassembly.payload.is_none());
+        assert_eq!(reassembly.missing_count, u32::MAX - 1);
+        assert_eq!(reassembly.missing.len(), MAX_LISTED_MISSING);
+        assert_eq!(reassembly.missing[0], 1);
+    }
+
+    #[test]
+    fn test_empty_payload_is_one_part() {
+        let parts = split(&[], 10).unwrap();
+        assert_eq!(parts.len(), 1);
+        assert_eq!(
+            reassemble(&parts).unwrap().payload.unwrap(),
+            Vec::<u8>::new()
+        );
+    }
+
+    #[test]
+    fn test_part_rejects_bad_sequence() {
+        let mut bytes = split(b"abc", 10).unwrap()[0].as_bytes();
+        bytes[15] = 1;
+        assert!(Part::try_from(&bytes[..]).is_err());
+    }
+}
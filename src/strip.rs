@@ -0,0 +1,137 @@
+use std::str::FromStr;
+
+use crate::{chunk::Chunk, chunk_type::ChunkType, png::Png, registry};
+
+/// Ancillary chunks that change how an image is displayed, kept unless a
+/// different allowlist is given.
+pub const DEFAULT_KEEP: [&str; 12] = [
+    "tRNS", "cHRM", "gAMA", "iCCP", "sBIT", "sRGB", "cICP", "mDCV", "cLLI", "acTL", "fcTL", "fdAT",
+];
+
+/// A chunk type, or one of the groups `text` and `private`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChunkClass {
+    Type(ChunkType),
+    /// tEXt, zTXt and iTXt.
+    Text,
+    /// Private chunks that are not in the registry, such as hidden messages.
+    Private,
+}
+
+impl FromStr for ChunkClass {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(ChunkClass::Text),
+            "private" => Ok(ChunkClass::Private),
+            _ => Ok(ChunkClass::Type(ChunkType::from_str(s)?)),
+        }
+    }
+}
+
+impl ChunkClass {
+    pub fn matches(&self, chunk: &Chunk) -> bool {
+        let chunk_type = chunk.chunk_type();
+        match self {
+            ChunkClass::Type(t) => chunk_type == t,
+            ChunkClass::Text => ["tEXt", "zTXt", "iTXt"].contains(&&chunk_type.to_string()[..]),
+            ChunkClass::Private => {
+                !chunk_type.is_public() && registry::lookup(chunk_type).is_none()
+            }
+        }
+    }
+}
+
+/// Which ancillary chunks to strip. Critical chunks are always kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rule {
+    /// Remove every ancillary chunk not matching one of these.
+    Keep(Vec<ChunkClass>),
+    /// Remove the chunks matching one of these.
+    Remove(Vec<ChunkClass>),
+}
+
+impl Default for Rule {
+    fn default() -> Self {
+        Rule::Keep(
+            DEFAULT_KEEP
+                .iter()
+                .map(|t| ChunkClass::Type(ChunkType::from_str(t).unwrap()))
+                .collect(),
+        )
+    }
+}
+
+/// Removes the chunks selected by `rule` from `png` and returns them.
+pub fn strip(png: &mut Png, rule: &Rule) -> crate::Result<Vec<Chunk>> {
+    if let Rule::Remove(classes) = rule {
+        for class in classes.iter() {
+            if let ChunkClass::Type(t) = class {
+                if t.is_critical() {
+                    return Err(format!("cannot strip critical chunk {t}").into());
+                }
+            }
+        }
+    }
+
+    Ok(png.remove_where(|c| {
+        !c.chunk_type().is_critical()
+            && match rule {
+                Rule::Keep(classes) => !classes.iter().any(|class| class.matches(c)),
+                Rule::Remove(classes) => classes.iter().any(|class| class.matches(c)),
+            }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::Encoder;
+    use crate::ihdr::{ColorType, Ihdr};
+
+    fn testing_png() -> Png {
+        let mut png = Encoder::new(Ihdr::new(2, 2, ColorType::Grayscale, 8).unwrap())
+            .encode(&[1, 2, 3, 4])
+            .unwrap();
+        for (chunk_type, data) in [
+            ("gAMA", "\0\0\0\0"),
+            ("tEXt", "Author\0me"),
+            ("iTXt", "Title\0\0\0\0\0x"),
+            ("tIME", "\x07\x01\x01\0\0\0\0"),
+            ("ruSt", "hidden"),
+        ] {
+            png.append_chunk(
+                Chunk::chunk_from_strings(chunk_type.to_string(), data.to_string()).unwrap(),
+            );
+        }
+        png
+    }
+
+    fn types(chunks: &[Chunk]) -> Vec<String> {
+        chunks.iter().map(|c| c.chunk_type().to_string()).collect()
+    }
+
+    #[test]
+    fn test_default_keeps_colour_chunks() {
+        let mut png = testing_png();
+        let removed = strip(&mut png, &Rule::default()).unwrap();
+        assert_eq!(types(&removed), vec!["tEXt", "iTXt", "tIME", "ruSt"]);
+        assert_eq!(types(&png.chunks()), vec!["IHDR", "IDAT", "gAMA", "IEND"]);
+    }
+
+    #[test]
+    fn test_remove_groups() {
+        let mut png = testing_png();
+        let rule = Rule::Remove(vec![
+            ChunkClass::from_str("text").unwrap(),
+            ChunkClass::from_str("private").unwrap(),
+        ]);
+        let removed = strip(&mut png, &rule).unwrap();
+        assert_eq!(types(&removed), vec!["tEXt", "iTXt", "ruSt"]);
+        assert!(png.chunk_by_type("tIME").is_some());
+
+        let critical = Rule::Remove(vec![ChunkClass::from_str("IDAT").unwrap()]);
+        assert!(strip(&mut png, &critical).is_err());
+    }
+}